@@ -0,0 +1,428 @@
+use ethers::abi::token::{LenientTokenizer, Tokenizer};
+use ethers::abi::{Abi, AbiParser, Event, Function, ParamType, RawLog, Token};
+use ethers::types::{Address, H256, I256, U256};
+use ethers::utils::{hex, to_checksum};
+use serde::{Deserialize, Serialize};
+
+/// Converts an ABI in its standard JSON form (e.g. the artifact's `JsonAbi`)
+/// into an ethers `Abi`.
+pub fn from_json<T: Serialize>(abi: &T) -> Option<Abi> {
+    let value = serde_json::to_value(abi).ok()?;
+    serde_json::from_value(value).ok()
+}
+
+/// Canonical `name(type1,type2)` signature used for selector hashing.
+pub fn function_signature(f: &Function) -> String {
+    let inputs: Vec<String> = f.inputs.iter().map(|p| p.kind.to_string()).collect();
+    format!("{}({})", f.name, inputs.join(","))
+}
+
+pub fn event_signature(e: &Event) -> String {
+    let inputs: Vec<String> = e.inputs.iter().map(|p| p.kind.to_string()).collect();
+    format!("{}({})", e.name, inputs.join(","))
+}
+
+pub fn function_selector(f: &Function) -> String {
+    format!("0x{}", hex::encode(f.short_signature()))
+}
+
+pub fn event_topic(e: &Event) -> String {
+    format!("0x{}", hex::encode(e.signature()))
+}
+
+#[derive(Serialize)]
+pub struct FunctionSelector {
+    pub sig: String,
+    pub selector: String,
+}
+
+#[derive(Serialize)]
+pub struct EventTopic {
+    pub sig: String,
+    pub topic: String,
+}
+
+#[derive(Serialize)]
+pub struct Selectors {
+    pub functions: Vec<FunctionSelector>,
+    pub events: Vec<EventTopic>,
+}
+
+pub fn selectors(abi: &Abi) -> Selectors {
+    Selectors {
+        functions: abi.functions()
+            .map(|f| FunctionSelector { sig: function_signature(f), selector: function_selector(f) })
+            .collect(),
+        events: abi.events()
+            .map(|e| EventTopic { sig: event_signature(e), topic: event_topic(e) })
+            .collect(),
+    }
+}
+
+/// An ABI entry `fragment_selectors` couldn't parse, by its position.
+#[derive(Serialize)]
+pub struct FragmentError {
+    pub index: usize,
+    pub error: String,
+}
+
+/// Fills in what the ABI spec lets hand-written fragments leave out but
+/// ethers requires: `type` (a function), empty `inputs`/`outputs` and
+/// parameter names.
+fn with_defaults(fragment: &serde_json::Value) -> serde_json::Value {
+    fn name_params(params: &mut serde_json::Value) {
+        for param in params.as_array_mut().into_iter().flatten() {
+            if let Some(param) = param.as_object_mut() {
+                param.entry("name").or_insert_with(|| "".into());
+                if let Some(components) = param.get_mut("components") {
+                    name_params(components);
+                }
+            }
+        }
+    }
+    let mut fragment = fragment.clone();
+    if let Some(object) = fragment.as_object_mut() {
+        let kind = object.entry("type").or_insert_with(|| "function".into()).clone();
+        object.entry("inputs").or_insert_with(|| serde_json::json!([]));
+        if kind == "function" {
+            object.entry("outputs").or_insert_with(|| serde_json::json!([]));
+        }
+        for key in ["inputs", "outputs"] {
+            if let Some(params) = object.get_mut(key) {
+                name_params(params);
+            }
+        }
+    }
+    fragment
+}
+
+/// Whether solc could have produced `kind`: ethers parses any size of
+/// `uintN`, `intN` and `bytesN`.
+fn is_valid_type(kind: &ParamType) -> bool {
+    match kind {
+        ParamType::Int(bits) | ParamType::Uint(bits) => *bits > 0 && *bits <= 256 && bits % 8 == 0,
+        ParamType::FixedBytes(len) => *len > 0 && *len <= 32,
+        ParamType::Array(inner) | ParamType::FixedArray(inner, _) => is_valid_type(inner),
+        ParamType::Tuple(members) => members.iter().all(is_valid_type),
+        _ => true,
+    }
+}
+
+/// `selectors` for a raw JSON ABI that hasn't been validated, one entry at
+/// a time so a malformed fragment doesn't hide the rest. Entries that are
+/// neither functions nor events (constructors, errors, ...) are skipped.
+pub fn fragment_selectors(fragments: &[serde_json::Value]) -> (Selectors, Vec<FragmentError>) {
+    let mut functions = Vec::new();
+    let mut events = Vec::new();
+    let mut errors = Vec::new();
+    for (index, fragment) in fragments.iter().enumerate() {
+        if !fragment.is_object() {
+            errors.push(FragmentError { index, error: "ABI entries must be objects".to_string() });
+            continue;
+        }
+        match serde_json::from_value::<Abi>(serde_json::Value::Array(vec![with_defaults(fragment)])) {
+            Ok(abi) => {
+                let mut kinds = abi.functions().flat_map(|f| f.inputs.iter().chain(&f.outputs).map(|p| &p.kind))
+                    .chain(abi.events().flat_map(|e| e.inputs.iter().map(|p| &p.kind)));
+                if let Some(kind) = kinds.find(|kind| !is_valid_type(kind)) {
+                    errors.push(FragmentError { index, error: format!("invalid type {}", kind) });
+                    continue;
+                }
+                let Selectors { functions: f, events: e } = selectors(&abi);
+                functions.extend(f);
+                events.extend(e);
+            }
+            Err(e) => errors.push(FragmentError { index, error: e.to_string() }),
+        }
+    }
+    (Selectors { functions, events }, errors)
+}
+
+/// A function as an interaction form needs it. Parameters keep their JSON
+/// ABI shape (`name`, `type`, `internalType`, tuple `components`).
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Method {
+    pub name: String,
+    pub signature: String,
+    pub selector: String,
+    pub state_mutability: String,
+    pub inputs: Vec<serde_json::Value>,
+    pub outputs: Vec<serde_json::Value>,
+}
+
+/// Functions split by whether calling them needs a transaction.
+#[derive(Serialize)]
+pub struct Methods {
+    /// `view` and `pure` functions.
+    pub read: Vec<Method>,
+    /// `nonpayable` and `payable` functions.
+    pub write: Vec<Method>,
+    /// The constructor's `{ inputs, stateMutability }`, if it has one.
+    pub constructor: Option<serde_json::Value>,
+}
+
+/// Groups the functions of `abi` (in its standard JSON form) in ABI order.
+pub fn methods<T: Serialize>(abi: &T) -> Option<Methods> {
+    let items = serde_json::to_value(abi).ok()?;
+    let mut methods = Methods { read: Vec::new(), write: Vec::new(), constructor: None };
+    for item in items.as_array()? {
+        let params = |field: &str| item.get(field).and_then(|p| p.as_array()).cloned().unwrap_or_default();
+        let mutability = item.get("stateMutability").and_then(|m| m.as_str()).unwrap_or("nonpayable").to_string();
+        match item.get("type").and_then(|t| t.as_str()) {
+            Some("function") => {
+                let function: Function = serde_json::from_value(item.clone()).ok()?;
+                let method = Method {
+                    name: function.name.clone(),
+                    signature: function_signature(&function),
+                    selector: function_selector(&function),
+                    inputs: params("inputs"),
+                    outputs: params("outputs"),
+                    state_mutability: mutability,
+                };
+                if matches!(method.state_mutability.as_str(), "view" | "pure") {
+                    methods.read.push(method);
+                } else {
+                    methods.write.push(method);
+                }
+            }
+            Some("constructor") => {
+                methods.constructor = Some(serde_json::json!({ "inputs": params("inputs"), "stateMutability": mutability }));
+            }
+            _ => {}
+        }
+    }
+    Some(methods)
+}
+
+/// The inverse of `token_to_json`: builds a token of type `kind` from a
+/// JSON value. Arrays and tuples are JSON arrays; everything else may be a
+/// string (numbers in decimal or `0x` hex), a number or a bool.
+pub fn json_to_token(kind: &ParamType, value: &serde_json::Value) -> Result<Token, String> {
+    let items = |expected: &str| value.as_array().ok_or_else(|| format!("Expected an array for {}, got {}", expected, value));
+    match kind {
+        ParamType::Array(inner) => Ok(Token::Array(items(&kind.to_string())?.iter()
+            .map(|v| json_to_token(inner, v))
+            .collect::<Result<_, _>>()?)),
+        ParamType::FixedArray(inner, len) => {
+            let values = items(&kind.to_string())?;
+            if values.len() != *len {
+                return Err(format!("Expected {} items for {}, got {}", len, kind, values.len()));
+            }
+            Ok(Token::FixedArray(values.iter().map(|v| json_to_token(inner, v)).collect::<Result<_, _>>()?))
+        }
+        ParamType::Tuple(kinds) => {
+            let values = items(&kind.to_string())?;
+            if values.len() != kinds.len() {
+                return Err(format!("Expected {} fields for {}, got {}", kinds.len(), kind, values.len()));
+            }
+            Ok(Token::Tuple(kinds.iter().zip(values).map(|(k, v)| json_to_token(k, v)).collect::<Result<_, _>>()?))
+        }
+        _ => {
+            let text = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.to_string(),
+                _ => return Err(format!("Expected a {} value, got {}", kind, value)),
+            };
+            LenientTokenizer::tokenize(kind, &text).map_err(|e| format!("Invalid {} value {}: {}", kind, text, e))
+        }
+    }
+}
+
+/// Renders a decoded token as JSON: integers as decimal strings (to keep
+/// full precision), addresses checksummed, bytes as 0x-prefixed hex.
+pub fn token_to_json(token: &Token) -> serde_json::Value {
+    match token {
+        Token::Address(addr) => serde_json::json!(to_checksum(addr, None)),
+        Token::Uint(v) => serde_json::json!(v.to_string()),
+        Token::Int(v) => serde_json::json!(I256::from_raw(*v).to_string()),
+        Token::Bool(b) => serde_json::json!(b),
+        Token::String(s) => serde_json::json!(s),
+        Token::Bytes(b) | Token::FixedBytes(b) => serde_json::json!(format!("0x{}", hex::encode(b))),
+        Token::Array(items) | Token::FixedArray(items) | Token::Tuple(items) => {
+            serde_json::Value::Array(items.iter().map(token_to_json).collect())
+        }
+    }
+}
+
+/// Decodes a word holding a value of elementary type `ty` (as solc spells
+/// it, e.g. `uint8`, `contract Token`, `enum Kind`). `bytesN` values are
+/// left-aligned, everything else right-aligned, as on the stack and in ABI
+/// encoding. Returns `None` for reference types.
+pub fn decode_word(ty: &str, word: U256) -> Option<serde_json::Value> {
+    let mut bytes = [0u8; 32];
+    word.to_big_endian(&mut bytes);
+
+    if ty == "bool" {
+        return Some(serde_json::json!(!word.is_zero()));
+    }
+    if ty == "address" || ty == "address payable" || ty.starts_with("contract ") {
+        return Some(serde_json::json!(to_checksum(&Address::from_slice(&bytes[12..]), None)));
+    }
+    if ty.starts_with("uint") || ty.starts_with("enum ") {
+        return Some(serde_json::json!(word.to_string()));
+    }
+    if let Some(bits) = ty.strip_prefix("int") {
+        let bits: usize = if bits.is_empty() { 256 } else { bits.parse().ok()? };
+        // Narrow values aren't always sign-extended, so do it here
+        let shift = 256usize.checked_sub(bits)?;
+        return Some(serde_json::json!(I256::from_raw(word << shift).asr(shift).to_string()));
+    }
+    if let Some(size) = ty.strip_prefix("bytes").and_then(|n| n.parse::<usize>().ok()) {
+        return Some(serde_json::json!(format!("0x{}", hex::encode(&bytes[..size.min(32)]))));
+    }
+    None
+}
+
+/// Parses `0x`-prefixed (or bare) hex into bytes.
+pub fn parse_hex(input: &str) -> Option<Vec<u8>> {
+    hex::decode(input.trim().trim_start_matches("0x")).ok()
+}
+
+/// Parses a signature such as `transfer(address,uint256)`.
+pub fn parse_function(signature: &str) -> Option<Function> {
+    AbiParser::default().parse_function(signature).ok()
+}
+
+/// Parses an event signature such as `Transfer(address indexed, address,
+/// uint256)`, with or without the leading `event`.
+pub fn parse_event(signature: &str) -> Option<Event> {
+    let signature = signature.trim();
+    let signature = if signature.starts_with("event ") { signature.to_string() } else { format!("event {}", signature) };
+    AbiParser::default().parse_event(&signature).ok()
+}
+
+/// Decodes calldata (selector included) against `f`, returning named args.
+pub fn decode_call(f: &Function, calldata: &[u8]) -> Option<Vec<serde_json::Value>> {
+    if calldata.len() < 4 || calldata[..4] != f.short_signature() {
+        return None;
+    }
+    let tokens = f.decode_input(&calldata[4..]).ok()?;
+    Some(f.inputs.iter().zip(tokens.iter()).map(|(param, token)| serde_json::json!({
+        "name": param.name,
+        "type": param.kind.to_string(),
+        "value": token_to_json(token),
+    })).collect())
+}
+
+/// A log as returned by `eth_getLogs` / receipts.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LogInput {
+    #[serde(default)]
+    pub address: Option<String>,
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub data: String,
+}
+
+/// Decodes `log` against the first event in `abis` whose topic matches
+/// topic0. Unmatched or undecodable logs are returned raw.
+pub fn decode_log(abis: &[(String, Abi)], log: &LogInput) -> serde_json::Value {
+    let raw = || serde_json::json!({ "decoded": false, "raw": log });
+
+    let topics: Option<Vec<H256>> = log.topics.iter()
+        .map(|t| parse_hex(t).filter(|b| b.len() == 32).map(|b| H256::from_slice(&b)))
+        .collect();
+    let (Some(topics), Some(data)) = (topics, parse_hex(&log.data)) else { return raw() };
+    let Some(topic0) = topics.first().copied() else { return raw() };
+
+    for (contract, abi) in abis {
+        for event in abi.events().filter(|e| !e.anonymous && e.signature() == topic0) {
+            let parsed = event.parse_log(RawLog { topics: topics.clone(), data: data.clone() });
+            if let Ok(parsed) = parsed {
+                let params: Vec<serde_json::Value> = parsed.params.iter().map(|p| serde_json::json!({
+                    "name": p.name,
+                    "value": token_to_json(&p.value),
+                })).collect();
+                return serde_json::json!({
+                    "decoded": true,
+                    "name": event.name,
+                    "signature": event_signature(event),
+                    "contract": contract,
+                    "address": log.address,
+                    "params": params,
+                });
+            }
+        }
+    }
+    raw()
+}
+
+/// `Error(string)`, what `require(cond, "reason")` and `revert("reason")` throw.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// `Panic(uint256)`, thrown by failed asserts, overflows and the like.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// What solc's panic codes stand for.
+fn panic_reason(code: U256) -> &'static str {
+    match code.low_u64() {
+        0x00 => "generic compiler-inserted panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic underflow or overflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "conversion to an invalid enum value",
+        0x22 => "incorrectly encoded storage byte array",
+        0x31 => "pop() on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to an uninitialized internal function",
+        _ => "unknown panic code",
+    }
+}
+
+/// Decodes revert data: `{ type: "revert", reason }` for `Error(string)`
+/// (or no data at all, with a `null` reason), `{ type: "panic", code,
+/// reason }` for `Panic(uint256)`, and `{ type: "customError", name,
+/// signature, contract, args }` for the first error in `abis` that decodes
+/// it. Anything else is `{ type: "unknown", selector, data }`.
+pub fn decode_revert(abis: &[(String, Abi)], data: &[u8]) -> serde_json::Value {
+    if data.is_empty() {
+        return serde_json::json!({ "type": "revert", "reason": null });
+    }
+    let (selector, rest) = data.split_at(data.len().min(4));
+    if selector == ERROR_SELECTOR {
+        if let Ok(tokens) = ethers::abi::decode(&[ParamType::String], rest) {
+            if let Some(Token::String(reason)) = tokens.into_iter().next() {
+                return serde_json::json!({ "type": "revert", "reason": reason });
+            }
+        }
+    }
+    if selector == PANIC_SELECTOR {
+        if let Ok(tokens) = ethers::abi::decode(&[ParamType::Uint(256)], rest) {
+            if let Some(Token::Uint(code)) = tokens.into_iter().next() {
+                return serde_json::json!({ "type": "panic", "code": format!("0x{:x}", code), "reason": panic_reason(code) });
+            }
+        }
+    }
+    for (contract, abi) in abis {
+        for error in abi.errors().filter(|e| e.signature()[..4] == *selector) {
+            let Ok(tokens) = error.decode(rest) else { continue };
+            let inputs: Vec<String> = error.inputs.iter().map(|p| p.kind.to_string()).collect();
+            let args: Vec<serde_json::Value> = error.inputs.iter().zip(tokens.iter()).map(|(param, token)| serde_json::json!({
+                "name": param.name,
+                "type": param.kind.to_string(),
+                "value": token_to_json(token),
+            })).collect();
+            return serde_json::json!({
+                "type": "customError",
+                "name": error.name,
+                "signature": format!("{}({})", error.name, inputs.join(",")),
+                "contract": contract,
+                "args": args,
+            });
+        }
+    }
+    serde_json::json!({
+        "type": "unknown",
+        "selector": format!("0x{}", hex::encode(selector)),
+        "data": format!("0x{}", hex::encode(data)),
+    })
+}
+
+/// Finds revert data in an error message from a node or `cast`, which
+/// quote it as `data: "0x..."` (or give it as the JSON-RPC error's `data`).
+pub fn revert_data_in(message: &str) -> Option<Vec<u8>> {
+    let start = message.rfind("data: \"0x")? + "data: \"".len();
+    let end = message[start..].find('"')? + start;
+    parse_hex(&message[start..end])
+}
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Slither's impact levels, least severe first so they order naturally.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Optimization,
+    Informational,
+    Low,
+    Medium,
+    High,
+}
+
+/// Where a finding points: a contract, function, variable or node.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    /// Relative to the project root, as slither reports it.
+    pub file: Option<String>,
+    pub lines: Vec<u64>,
+    /// Byte offset and length in `file`.
+    pub start: Option<u64>,
+    pub length: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Finding {
+    pub id: Option<String>,
+    pub detector: String,
+    pub impact: Severity,
+    pub confidence: Option<String>,
+    pub description: String,
+    pub locations: Vec<Location>,
+}
+
+/// Parses `slither --json -` output into findings of at least `min`
+/// impact, most severe first. A run slither itself reports as failed is
+/// an error with its message.
+pub fn findings(output: &str, min: Severity) -> Result<Vec<Finding>, String> {
+    let report: Value = serde_json::from_str(output)
+        .map_err(|e| format!("Unexpected slither output: {}", e))?;
+    if report.get("success").and_then(Value::as_bool) != Some(true) {
+        let error = report.get("error").and_then(Value::as_str).unwrap_or("unknown error");
+        return Err(format!("Slither failed: {}", error));
+    }
+    let detectors = report.pointer("/results/detectors").and_then(Value::as_array);
+    let str_field = |v: &Value, name: &str| v.get(name).and_then(Value::as_str).map(str::to_string);
+
+    let mut findings: Vec<Finding> = detectors.into_iter().flatten().filter_map(|d| {
+        let impact: Severity = serde_json::from_value(d.get("impact")?.clone()).ok()?;
+        if impact < min {
+            return None;
+        }
+        let locations = d.get("elements").and_then(Value::as_array).into_iter().flatten().map(|e| {
+            let mapping = e.get("source_mapping");
+            let number = |name: &str| mapping.and_then(|m| m.get(name)).and_then(Value::as_u64);
+            Location {
+                kind: str_field(e, "type"),
+                name: str_field(e, "name"),
+                file: mapping.and_then(|m| str_field(m, "filename_relative")),
+                lines: mapping.and_then(|m| m.get("lines")).and_then(Value::as_array)
+                    .map(|lines| lines.iter().filter_map(Value::as_u64).collect())
+                    .unwrap_or_default(),
+                start: number("start"),
+                length: number("length"),
+            }
+        }).collect();
+        Some(Finding {
+            id: str_field(d, "id"),
+            detector: str_field(d, "check")?,
+            impact,
+            confidence: str_field(d, "confidence"),
+            description: str_field(d, "description").unwrap_or_default().trim().to_string(),
+            locations,
+        })
+    }).collect();
+    findings.sort_by_key(|f| std::cmp::Reverse(f.impact));
+    Ok(findings)
+}
@@ -1,47 +1,146 @@
-use std::process::{Command, Child};
+use ethers::signers::{coins_bip39::English, MnemonicBuilder, Signer};
+use ethers::types::Address;
+use ethers::utils::hex;
+use serde::Serialize;
+use std::process::{Command, Child, ExitStatus};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use crate::ws::{self, Broadcast, WsMessage};
+
+const REAP_INTERVAL: Duration = Duration::from_secs(2);
+const MIN_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// How long anvil gets to exit after SIGTERM before it's SIGKILLed.
+pub const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(3);
+/// How many ports above the configured one are tried when it's taken.
+const PORT_SCAN_RANGE: u16 = 20;
+/// What anvil derives its dev accounts from without `--mnemonic`, which
+/// chasm never passes.
+const DEFAULT_MNEMONIC: &str = "test test test test test test test test test test test junk";
+const DEFAULT_ACCOUNT_COUNT: u32 = 10;
+
+/// Anvil's default dev accounts with their (well-known) private keys.
+/// Derived once, since each derivation runs the BIP-39 key stretching.
+pub fn dev_accounts() -> &'static [(Address, String)] {
+    static ACCOUNTS: OnceLock<Vec<(Address, String)>> = OnceLock::new();
+    ACCOUNTS.get_or_init(|| {
+        (0..DEFAULT_ACCOUNT_COUNT).filter_map(|index| {
+            let wallet = MnemonicBuilder::<English>::default().phrase(DEFAULT_MNEMONIC).index(index).ok()?.build().ok()?;
+            Some((wallet.address(), format!("0x{}", hex::encode(wallet.signer().to_bytes()))))
+        }).collect()
+    })
+}
+
+/// Most `-v`s anvil accepts.
+pub const MAX_VERBOSITY: u8 = 5;
+
+/// How much anvil writes to the stdout it shares with chasm.
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AnvilLogging {
+    /// `--silent`: not even the startup banner or RPC method names.
+    pub silent: bool,
+    /// How many `-v`s to pass; from 3 anvil prints traces of transactions.
+    pub verbosity: u8,
+}
+
+impl AnvilLogging {
+    fn apply(&self, cmd: &mut Command) {
+        if self.silent {
+            cmd.arg("--silent");
+        }
+        if self.verbosity > 0 {
+            cmd.arg(format!("-{}", "v".repeat(self.verbosity.min(MAX_VERBOSITY).into())));
+        }
+    }
+}
 
 pub struct AnvilNode {
     process: Option<Child>,
     port: u16,
     fork_url: Option<String>,
     fork_block: Option<u64>,
+    exit_status: Option<String>,
+    stop_grace: Duration,
+    logging: AnvilLogging,
 }
 
 impl AnvilNode {
-    pub fn new(port: u16) -> Self {
-        Self { process: None, port, fork_url: None, fork_block: None }
+    pub fn new(port: u16, stop_grace: Duration, logging: AnvilLogging) -> Self {
+        Self { process: None, port, fork_url: None, fork_block: None, exit_status: None, stop_grace, logging }
     }
 
     pub fn start(&mut self) -> anyhow::Result<()> {
-        let child = Command::new("anvil")
-            .arg("--port")
-            .arg(self.port.to_string())
-            .spawn()?;
-        
+        self.pick_port()?;
+        let mut cmd = Command::new("anvil");
+        cmd.arg("--port").arg(self.port.to_string());
+        self.logging.apply(&mut cmd);
+
+        let child = cmd.spawn()?;
+
         self.process = Some(child);
         self.fork_url = None;
         self.fork_block = None;
+        self.exit_status = None;
         Ok(())
     }
 
     pub fn start_fork(&mut self, fork_url: String, fork_block: Option<u64>) -> anyhow::Result<()> {
+        self.pick_port()?;
         let mut cmd = Command::new("anvil");
         cmd.arg("--port").arg(self.port.to_string());
         cmd.arg("--fork-url").arg(&fork_url);
         if let Some(block) = fork_block {
             cmd.arg("--fork-block-number").arg(block.to_string());
         }
+        self.logging.apply(&mut cmd);
 
         let child = cmd.spawn()?;
         self.process = Some(child);
         self.fork_url = Some(fork_url);
         self.fork_block = fork_block;
+        self.exit_status = None;
+        Ok(())
+    }
+
+    /// Moves to the first free port from the current one upward, so a port
+    /// held by another tool doesn't make anvil exit straight away. Checked
+    /// by binding it, so another process can still grab it before anvil does.
+    fn pick_port(&mut self) -> anyhow::Result<()> {
+        let free = (self.port..=self.port.saturating_add(PORT_SCAN_RANGE))
+            .find(|port| std::net::TcpListener::bind(("127.0.0.1", *port)).is_ok())
+            .ok_or_else(|| anyhow::anyhow!("Ports {} to {} are all in use", self.port, self.port.saturating_add(PORT_SCAN_RANGE)))?;
+        if free != self.port {
+            tracing::warn!("Port {} is in use, starting anvil on {} instead", self.port, free);
+            self.port = free;
+        }
         Ok(())
     }
 
+    /// Stops the node, blocking for up to the stop grace period while it
+    /// shuts down. Best-effort: failures to signal or reap are ignored.
     pub fn stop(&mut self) {
         if let Some(mut child) = self.process.take() {
-            let _ = child.kill();
+            terminate(&mut child, self.stop_grace);
+        }
+    }
+
+    /// Checks whether the child has exited without blocking. Returns the exit
+    /// status the first time an exit is observed and marks the node stopped.
+    pub fn poll_exit(&mut self) -> Option<ExitStatus> {
+        let child = self.process.as_mut()?;
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                self.process = None;
+                self.exit_status = Some(status.to_string());
+                Some(status)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("Failed to poll anvil on port {}: {}", self.port, e);
+                None
+            }
         }
     }
 
@@ -53,9 +152,74 @@ impl AnvilNode {
         self.port
     }
 
+    /// JSON-RPC URL of this node.
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
     pub fn fork_info(&self) -> (Option<String>, Option<u64>) {
         (self.fork_url.clone(), self.fork_block)
     }
+
+    /// Records the block a running fork was reset to with `anvil_reset`.
+    pub fn set_fork_block(&mut self, fork_block: Option<u64>) {
+        self.fork_block = fork_block;
+    }
+
+    pub fn logging(&self) -> AnvilLogging {
+        self.logging
+    }
+
+    /// Exit status of the last process if it died on its own.
+    pub fn exit_status(&self) -> Option<String> {
+        self.exit_status.clone()
+    }
+}
+
+/// Kills anvil along with anything it spawned, so the port is free again
+/// once this returns. `Child::kill` on Windows only ends the direct child,
+/// so use `taskkill /T` to take the whole tree down.
+#[cfg(windows)]
+fn terminate(child: &mut Child, _grace: Duration) {
+    let status = Command::new("taskkill")
+        .args(["/PID", &child.id().to_string(), "/T", "/F"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status();
+    if !status.is_ok_and(|s| s.success()) {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+/// Asks anvil to exit with SIGTERM so it can release the port cleanly, and
+/// falls back to SIGKILL if it's still running after the grace period.
+#[cfg(unix)]
+fn terminate(child: &mut Child, grace: Duration) {
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        let _ = child.kill();
+        let _ = child.wait();
+        return;
+    };
+    // SAFETY: `pid` is our own unreaped child, so it can't have been reused
+    if unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+        let deadline = Instant::now() + grace;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        tracing::warn!("anvil (pid {}) ignored SIGTERM, killing it", pid);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminate(child: &mut Child, _grace: Duration) {
+    let _ = child.kill();
+    let _ = child.wait();
 }
 
 impl Drop for AnvilNode {
@@ -63,3 +227,106 @@ impl Drop for AnvilNode {
         self.stop();
     }
 }
+
+/// Periodically reaps both anvil children. The primary node is restarted with
+/// exponential backoff; a dead fork is only reported, since it usually died
+/// because of its upstream RPC and restarting it would just fail again.
+pub fn spawn_reaper(
+    primary: Arc<Mutex<AnvilNode>>,
+    fork: Arc<Mutex<AnvilNode>>,
+    tx: broadcast::Sender<Broadcast>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        let mut backoff = MIN_RESTART_BACKOFF;
+        let mut restart_at: Option<Instant> = None;
+        let mut last_restart: Option<Instant> = None;
+
+        loop {
+            interval.tick().await;
+
+            let died = primary.lock().unwrap().poll_exit().map(|status| status.to_string());
+            if let Some(status) = died {
+                // Reset the backoff if the node had been up for a while.
+                backoff = match last_restart {
+                    Some(at) if at.elapsed() < MAX_RESTART_BACKOFF => (backoff * 2).min(MAX_RESTART_BACKOFF),
+                    _ => MIN_RESTART_BACKOFF,
+                };
+                let port = primary.lock().unwrap().port();
+                tracing::error!("Primary anvil on port {} exited ({}), restarting in {:?}", port, status, backoff);
+                ws::broadcast(&tx, &anvil_died_msg("primary", port, &status, true));
+                restart_at = Some(Instant::now() + backoff);
+            }
+
+            if restart_at.is_some_and(|at| Instant::now() >= at) {
+                let mut node = primary.lock().unwrap();
+                match node.start() {
+                    Ok(_) => {
+                        tracing::info!("Primary anvil restarted on port {}", node.port());
+                        restart_at = None;
+                        last_restart = Some(Instant::now());
+                    }
+                    Err(e) => {
+                        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+                        tracing::error!("Failed to restart anvil: {}, retrying in {:?}", e, backoff);
+                        restart_at = Some(Instant::now() + backoff);
+                    }
+                }
+            }
+
+            let mut node = fork.lock().unwrap();
+            if let Some(status) = node.poll_exit() {
+                tracing::error!("Forked anvil on port {} exited ({})", node.port(), status);
+                ws::broadcast(&tx, &anvil_died_msg("fork", node.port(), &status.to_string(), false));
+            }
+        }
+    });
+}
+
+fn anvil_died_msg(node: &str, port: u16, status: &str, restarting: bool) -> WsMessage {
+    WsMessage::AnvilDied {
+        node: node.to_string(),
+        port,
+        status: status.to_string(),
+        restarting,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    fn wait_until_listening(port: u16) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+
+    #[test]
+    fn stop_then_start_reuses_the_port() {
+        if Command::new("anvil").arg("--version").output().is_err() {
+            eprintln!("anvil not installed, skipping");
+            return;
+        }
+        let logging = AnvilLogging { silent: true, verbosity: 0 };
+        let mut node = AnvilNode::new(18645, DEFAULT_STOP_GRACE, logging);
+        for _ in 0..3 {
+            node.start().unwrap();
+            let port = node.port();
+            assert!(wait_until_listening(port), "anvil never listened on {}", port);
+            node.stop();
+            assert!(TcpListener::bind(("127.0.0.1", port)).is_ok(), "port {} still bound after stop", port);
+            node.start().unwrap();
+            assert_eq!(node.port(), port, "restart moved off the freed port");
+            assert!(wait_until_listening(port), "restarted anvil never listened on {}", port);
+            assert!(node.poll_exit().is_none(), "restarted anvil exited");
+            node.stop();
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use crate::error::ApiError;
+
+/// The `--auth-token` every API request must present, if one was set.
+pub type AuthToken = Option<Arc<str>>;
+
+/// Compares without short-circuiting so the token can't be guessed a byte
+/// at a time from response timings.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// `Authorization: Bearer <token>`, or `?token=<token>` on `/ws` since
+/// browsers can't set headers on a WebSocket upgrade.
+fn presented_token(req: &Request) -> Option<&str> {
+    let bearer = req.headers().get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if bearer.is_some() || req.uri().path() != "/ws" {
+        return bearer;
+    }
+    req.uri().query()?.split('&').find_map(|pair| pair.strip_prefix("token="))
+}
+
+/// Rejects requests without the bearer token with 401. A no-op when no
+/// token is configured.
+pub async fn require_token(State(token): State<AuthToken>, req: Request, next: Next) -> Response {
+    let Some(expected) = token else { return next.run(req).await };
+    match presented_token(&req) {
+        Some(given) if token_matches(given, &expected) => next.run(req).await,
+        Some(_) => ApiError::Unauthorized("Invalid auth token".to_string()).into_response(),
+        None => ApiError::Unauthorized("Missing bearer token".to_string()).into_response(),
+    }
+}
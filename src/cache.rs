@@ -0,0 +1,70 @@
+use ethers::utils::{hex, keccak256};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use crate::compiler::{ContractData, SourceFilter};
+use crate::project;
+
+/// Bumped whenever the cached payload's shape changes.
+const CACHE_FORMAT: u32 = 3;
+
+/// Project files that change compiler settings rather than sources.
+pub const CONFIG_FILES: [&str; 2] = ["foundry.toml", "remappings.txt"];
+
+#[derive(Deserialize)]
+struct CacheFile {
+    key: String,
+    contracts: Vec<ContractData>,
+}
+
+/// Hash of everything that determines the compile output: the contents of
+/// every source, the config files, the pinned or detected solc version and the
+/// `--source`/`--ignore` filters. Dependencies under `lib/` and `node_modules/` are not
+/// hashed; the background refresh after a cache hit picks those changes up.
+pub fn key(root: &Path, source_filter: &SourceFilter, solc: Option<&semver::Version>, via_ir: bool) -> String {
+    let files = source_filter.files(root);
+    let mut digests = Vec::new();
+    digests.extend_from_slice(&CACHE_FORMAT.to_be_bytes());
+    digests.extend_from_slice(env!("CARGO_PKG_VERSION").as_bytes());
+    let configs = CONFIG_FILES.iter().map(|name| root.join(name));
+    for path in files.iter().cloned().chain(configs) {
+        let contents = std::fs::read(&path).unwrap_or_default();
+        digests.extend_from_slice(path.to_string_lossy().as_bytes());
+        digests.extend_from_slice(&keccak256(contents));
+    }
+    let solc = solc.map(ToString::to_string).or_else(|| project::detect_solc_version(&files));
+    digests.extend_from_slice(solc.unwrap_or_default().as_bytes());
+    digests.push(via_ir as u8);
+    for pattern in &source_filter.include {
+        digests.push(b'+');
+        digests.extend_from_slice(pattern.as_str().as_bytes());
+    }
+    for pattern in &source_filter.exclude {
+        digests.push(b'-');
+        digests.extend_from_slice(pattern.as_str().as_bytes());
+    }
+    hex::encode(keccak256(digests))
+}
+
+/// The cached artifacts for `root`, if they were produced under `key`.
+pub fn load(root: &Path, key: &str) -> Option<Vec<ContractData>> {
+    let bytes = std::fs::read(cache_path(root)).ok()?;
+    let cache: CacheFile = serde_json::from_slice(&bytes).ok()?;
+    (cache.key == key).then_some(cache.contracts)
+}
+
+pub fn store(root: &Path, key: &str, contracts: &[ContractData]) {
+    let path = cache_path(root);
+    let cache = serde_json::json!({ "key": key, "contracts": contracts });
+    let result = path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, serde_json::to_vec(&cache)?));
+    if let Err(e) = result {
+        tracing::warn!("Failed to write compile cache {}: {}", path.display(), e);
+    }
+}
+
+/// One cache file per project root, outside the project itself.
+fn cache_path(root: &Path) -> PathBuf {
+    let id = hex::encode(&keccak256(root.to_string_lossy().as_bytes())[..8]);
+    std::env::temp_dir().join("chasm-compile-cache").join(format!("{}.json", id))
+}
@@ -0,0 +1,92 @@
+use ethers::abi::Abi;
+use serde::Serialize;
+use serde_json::Value;
+use crate::abi;
+
+/// One frame of a `callTracer` result, with its input and output decoded
+/// when a compiled contract has a function with the called selector.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallNode {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub value: Option<String>,
+    pub gas: Option<String>,
+    pub gas_used: Option<String>,
+    pub input: String,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub revert_reason: Option<String>,
+    /// `output` of a failed call, decoded (see `abi::decode_revert`).
+    pub revert: Option<Value>,
+    /// The first 4 bytes of `input`, if it has them.
+    pub selector: Option<String>,
+    /// The compiled contract whose ABI decoded the call.
+    pub contract: Option<String>,
+    /// The function's full signature, e.g. `transfer(address,uint256)`.
+    pub function: Option<String>,
+    /// `{ name, type, value }` per argument.
+    pub args: Option<Vec<Value>>,
+    /// `{ name, type, value }` per return value; reverted calls have none.
+    pub outputs: Option<Vec<Value>>,
+    pub calls: Vec<CallNode>,
+}
+
+/// Builds the tree from a `callTracer` frame, decoding every node against
+/// the first function in `abis` whose selector matches and whose argument
+/// encoding checks out. Calls nothing compiled knows stay raw.
+pub fn decode(frame: &Value, abis: &[(String, Abi)]) -> CallNode {
+    let field = |name: &str| frame.get(name).and_then(Value::as_str).map(str::to_string);
+    let input = field("input").unwrap_or_else(|| "0x".to_string());
+    let output = field("output");
+    let error = field("error");
+    let calldata = abi::parse_hex(&input).unwrap_or_default();
+    let selector = (calldata.len() >= 4).then(|| format!("0x{}", ethers::utils::hex::encode(&calldata[..4])));
+
+    let mut node = CallNode {
+        kind: field("type").unwrap_or_else(|| "CALL".to_string()),
+        from: field("from"),
+        to: field("to"),
+        value: field("value"),
+        gas: field("gas"),
+        gas_used: field("gasUsed"),
+        input,
+        output,
+        error,
+        revert_reason: field("revertReason"),
+        revert: None,
+        selector,
+        contract: None,
+        function: None,
+        args: None,
+        outputs: None,
+        calls: frame.get("calls").and_then(Value::as_array)
+            .map(|calls| calls.iter().map(|call| decode(call, abis)).collect())
+            .unwrap_or_default(),
+    };
+
+    if node.error.is_some() {
+        let data = node.output.as_deref().and_then(abi::parse_hex).unwrap_or_default();
+        node.revert = Some(abi::decode_revert(abis, &data));
+    }
+    let decoded = abis.iter()
+        .flat_map(|(contract, abi)| abi.functions().map(move |f| (contract, f)))
+        .find_map(|(contract, f)| Some((contract, f, abi::decode_call(f, &calldata)?)));
+    if let Some((contract, f, args)) = decoded {
+        node.outputs = node.error.is_none()
+            .then(|| node.output.as_deref().and_then(abi::parse_hex))
+            .flatten()
+            .and_then(|data| f.decode_output(&data).ok())
+            .map(|tokens| f.outputs.iter().zip(tokens.iter()).map(|(param, token)| serde_json::json!({
+                "name": param.name,
+                "type": param.kind.to_string(),
+                "value": abi::token_to_json(token),
+            })).collect());
+        node.contract = Some(contract.clone());
+        node.function = Some(abi::function_signature(f));
+        node.args = Some(args);
+    }
+    node
+}
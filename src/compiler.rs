@@ -1,79 +1,675 @@
 use anyhow::Result;
-use foundry_compilers::artifacts::ConfigurableContractArtifact;
-use foundry_compilers::{Project, ProjectPathsConfig};
-use std::path::PathBuf;
+use foundry_compilers::artifacts::{ast::Ast, output_selection::ContractOutputSelection, remappings::Remapping, ConfigurableContractArtifact};
+use foundry_compilers::solc::{Solc, SolcCompiler};
+use foundry_compilers::{multi::{MultiCompiler, MultiCompilerError, MultiCompilerSettings}, CompilationError, ConfigurableArtifacts, Project, ProjectPathsConfig};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use crate::abi;
+use crate::cache;
+use crate::debug::ContractMap;
+use crate::metrics::Metrics;
+use crate::project;
+use crate::ws::{self, Broadcast, WsMessage};
 
 pub struct Compiler {
     project: Project,
 }
 
 impl Compiler {
-    pub fn new(root: PathBuf) -> Result<Self> {
-        // Attempt to find contracts folder
-        let contracts_dir = root.join("contracts");
-        let src_path = if contracts_dir.exists() {
-            contracts_dir
-        } else {
-            root.clone() // Fallback to root if contracts dir missing
-        };
+    /// A compiler for the project at `root`. With `solc`, every source is
+    /// compiled by that binary; otherwise foundry-compilers picks (and
+    /// installs) a version per source from its pragmas. `via_ir` compiles
+    /// through the Yul IR pipeline.
+    pub fn new(root: PathBuf, solc: Option<Solc>, extra_dirs: &[PathBuf], via_ir: bool) -> Result<Self> {
+        let src_path = project::source_dir(&root);
 
         // Create a temporary cache directory for this compilation session
         let cache_dir = std::env::temp_dir().join(format!("chasm-cache-{}", std::process::id()));
         let artifacts_dir = std::env::temp_dir().join(format!("chasm-artifacts-{}", std::process::id()));
 
         // Build paths configuration explicitly, without relying on foundry.toml
-        let paths = ProjectPathsConfig::builder()
+        // remappings.txt comes first, then remappings derived from the libs
+        // for prefixes it doesn't map, which covers npm-style `@scope/pkg/...`
+        // imports once node_modules is among them. Giving any remappings
+        // turns off foundry-compilers' own derivation, so both are listed.
+        let libs = project::lib_dirs(&root);
+        let mut remappings = project::file_remappings(&root);
+        let derived: Vec<Remapping> = libs.iter().flat_map(|lib| Remapping::find_many(lib)).collect();
+        for remapping in derived {
+            if !remappings.iter().any(|r| r.context == remapping.context && r.name == remapping.name) {
+                remappings.push(remapping);
+            }
+        }
+        let mut builder = ProjectPathsConfig::builder()
             .root(&root)
             .sources(&src_path)
             .artifacts(&artifacts_dir)
             .cache(&cache_dir)
-            .build_infos(&artifacts_dir.join("build-info"))
-            .build()?;
+            .build_infos(artifacts_dir.join("build-info"))
+            .libs(libs)
+            .remappings(remappings);
+        if let Some(node_modules) = project::node_modules_dir(&root) {
+            builder = builder.allowed_path(&node_modules).include_path(&node_modules);
+        }
+        for dir in extra_dirs {
+            builder = builder.allowed_path(dir).include_path(dir);
+        }
+        let paths = builder.build()?;
 
         let project = Project::builder()
             .paths(paths)
+            .settings(solc_settings(via_ir))
+            .artifacts(ConfigurableArtifacts::new([ContractOutputSelection::StorageLayout], []))
             .ephemeral()
             .no_artifacts()
-            .build(Default::default())?;
+            .build(MultiCompiler {
+                solc: Some(solc.map_or(SolcCompiler::AutoDetect, SolcCompiler::Specific)),
+                ..Default::default()
+            })?;
 
         Ok(Self { project })
     }
 
-    pub fn compile(&self) -> Result<Vec<(String, ConfigurableContractArtifact)>> {
-        let output = self.project.compile()?;
+    /// The settings solc is given, as reported in `compile_success`.
+    pub fn settings(&self) -> CompileSettings {
+        let settings = &self.project.settings.solc.settings;
+        CompileSettings {
+            optimizer_enabled: settings.optimizer.enabled.unwrap_or(false),
+            optimizer_runs: settings.optimizer.runs,
+            evm_version: settings.evm_version.map(|v| v.to_string()),
+            via_ir: settings.via_ir.unwrap_or(false),
+        }
+    }
+
+    /// Import remappings the project resolves with, as `prefix=path`.
+    pub fn remappings(&self) -> Vec<String> {
+        self.project.paths.remappings.iter().map(ToString::to_string).collect()
+    }
+
+    pub fn compile(&self) -> Result<Vec<ContractData>> {
+        self.collect(self.project.compile()?)
+    }
+
+    /// Compiles only `files` plus whatever they import.
+    pub fn compile_files(&self, files: Vec<PathBuf>) -> Result<Vec<ContractData>> {
+        self.collect(self.project.compile_files(files)?)
+    }
+
+    /// Compiles `files` (or the whole project) and returns only the
+    /// compiler's errors and warnings, without artifacts.
+    pub fn diagnostics(&self, files: Option<Vec<PathBuf>>) -> Result<Vec<Diagnostic>> {
+        let output = match files {
+            Some(files) => self.project.compile_files(files)?,
+            None => self.project.compile()?,
+        };
+        let root = self.project.root();
+        let ignored = &self.project.ignored_error_codes;
+        Ok(output.output().errors.iter()
+            .filter(|e| !e.error_code().is_some_and(|code| ignored.contains(&code)))
+            .map(|e| Diagnostic::new(e, root))
+            .collect())
+    }
+
+    /// Compiles `files` (or the whole project) with build info on and
+    /// returns the Standard JSON input of every solc run.
+    pub fn solc_inputs(mut self, files: Option<Vec<PathBuf>>) -> Result<Vec<SolcInput>> {
+        self.project.build_info = true;
+        let output = match files {
+            Some(files) => self.project.compile_files(files)?,
+            None => self.project.compile()?,
+        };
+        if output.has_compiler_errors() {
+            return Err(anyhow::anyhow!("{output}"));
+        }
+        Ok(output.output().build_infos.iter().filter_map(|info| {
+            let text = |key: &str| info.build_info.get(key)?.as_str().map(str::to_string);
+            // The recorded input also carries foundry's own fields (version,
+            // CLI paths), which aren't part of the standard JSON
+            let recorded = info.build_info.get("input")?.as_object()?;
+            let input = ["language", "sources", "settings"].iter()
+                .filter_map(|key| Some((key.to_string(), recorded.get(*key)?.clone())))
+                .collect();
+            Some(SolcInput { solc_version: text("solcVersion")?, solc_long_version: text("solcLongVersion")?, input })
+        }).collect())
+    }
+
+    fn collect(&self, output: foundry_compilers::ProjectCompileOutput) -> Result<Vec<ContractData>> {
         if output.has_compiler_errors() {
             return Err(anyhow::anyhow!("{output}"));
         }
-        
+
+        // Each solc invocation numbers its input sources from 0; source maps
+        // refer to those ids, so group them per build
+        let root = self.project.root();
+        let mut source_lists: HashMap<String, Vec<String>> = HashMap::new();
+        let mut versions: HashMap<String, String> = HashMap::new();
+        for (path, files) in &output.output().sources.0 {
+            let rel = path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string();
+            for file in files {
+                versions.entry(file.build_id.clone()).or_insert_with(|| file.version.to_string());
+                let list = source_lists.entry(file.build_id.clone()).or_default();
+                let id = file.source_file.id as usize;
+                if list.len() <= id {
+                    list.resize(id + 1, String::new());
+                }
+                list[id] = rel.clone();
+            }
+        }
+
         Ok(output.into_artifacts()
-            .map(|(id, artifact)| (id.name, artifact))
+            .map(|(id, mut artifact)| {
+                let ast = artifact.ast.take();
+                let source_list = source_lists.get(&id.build_id).cloned().unwrap_or_default();
+                let solc_version = versions.get(&id.build_id).cloned();
+                let deployed_size = deployed_size(&artifact);
+                ContractData { name: id.name, artifact, source_list, solc_version, deployed_size, ast }
+            })
             .collect())
     }
+}
+
+/// The settings every compile passes to solc.
+fn solc_settings(via_ir: bool) -> MultiCompilerSettings {
+    // Also request each source's AST, used for symbol outlines, and
+    // storage layouts for decoding live storage
+    let mut settings = MultiCompilerSettings::default();
+    for file_selection in settings.solc.settings.output_selection.0.values_mut() {
+        file_selection.insert(String::new(), vec!["ast".to_string()]);
+        file_selection.entry("*".to_string()).or_default().push("storageLayout".to_string());
+    }
+    if via_ir {
+        settings.solc.settings.via_ir = Some(true);
+    }
+    settings
+}
+
+/// The solc settings that shape the bytecode, as sent in `compile_success`.
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileSettings {
+    pub optimizer_enabled: bool,
+    pub optimizer_runs: Option<usize>,
+    /// As requested; solc releases older than it fall back to the newest
+    /// EVM version they support.
+    pub evm_version: Option<String>,
+    pub via_ir: bool,
+}
+
+/// What one solc run was given, as needed to reproduce or verify it.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolcInput {
+    pub solc_version: String,
+    pub solc_long_version: String,
+    /// `{ language, sources, settings }`, source paths relative to the root.
+    pub input: serde_json::Map<String, serde_json::Value>,
+}
+
+/// EIP-170's cap on deployed code size, in force on mainnet.
+pub const EIP170_SIZE_LIMIT: usize = 24_576;
+
+/// Byte length of the runtime code. Unlinked code counts each library
+/// placeholder as the 20-byte address it will be replaced with.
+fn deployed_size(artifact: &ConfigurableContractArtifact) -> usize {
+    let Some(object) = artifact.deployed_bytecode.as_ref()
+        .and_then(|d| d.bytecode.as_ref())
+        .map(|b| &b.object) else { return 0 };
+    match object.as_str() {
+        Some(unlinked) => unlinked.trim_start_matches("0x").len() / 2,
+        None => object.bytes_len(),
+    }
+}
 
-    pub fn compile_to_json(&self) -> Result<String> {
-        let artifacts = self.compile()?;
-        
-        #[derive(serde::Serialize)]
-        struct CompileSuccess {
-             r#type: String,
-             contracts: Vec<ContractData>,
+/// One compiler error, warning or info message.
+#[derive(serde::Serialize)]
+pub struct Diagnostic {
+    /// `error`, `warning` or `info`.
+    pub severity: &'static str,
+    pub message: String,
+    /// solc's numeric error code, e.g. `2072` for an unused variable.
+    #[serde(rename = "errorCode")]
+    pub error_code: Option<u64>,
+    /// Project-relative file with the `start..end` byte range it refers to.
+    pub file: Option<String>,
+    pub start: Option<i32>,
+    pub end: Option<i32>,
+    /// The compiler's own rendering, with the offending source excerpt.
+    pub formatted: String,
+}
+
+impl Diagnostic {
+    pub fn new(error: &MultiCompilerError, root: &Path) -> Self {
+        let severity = if error.is_error() {
+            "error"
+        } else if error.is_warning() {
+            "warning"
+        } else {
+            "info"
+        };
+        let message = match error {
+            MultiCompilerError::Solc(e) => e.message.clone(),
+            MultiCompilerError::Vyper(e) => e.message.clone(),
+        };
+        let location = error.source_location();
+        Self {
+            severity,
+            message,
+            error_code: error.error_code(),
+            file: location.as_ref().map(|l| {
+                let path = Path::new(&l.file);
+                path.strip_prefix(root).unwrap_or(path).to_string_lossy().to_string()
+            }),
+            start: location.as_ref().map(|l| l.start),
+            end: location.as_ref().map(|l| l.end),
+            formatted: error.to_string(),
         }
-        
-        #[derive(serde::Serialize)]
-        struct ContractData {
-             name: String,
-             artifact: ConfigurableContractArtifact, 
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ContractData {
+    pub name: String,
+    /// Includes `bytecode`/`deployedBytecode` with their `object` and
+    /// `sourceMap` (solc's compressed `s:l:f:j:m;...` format), and `id`, the
+    /// index of the contract's own file in `source_list`.
+    pub artifact: ConfigurableContractArtifact,
+    /// Source paths (relative to the root) indexed by the `f` field of the
+    /// source maps. Ids without a source are empty strings.
+    #[serde(rename = "sourceList", default)]
+    pub source_list: Vec<String>,
+    /// The solc that compiled this contract, as reported by the build.
+    #[serde(rename = "solcVersion", default)]
+    pub solc_version: Option<String>,
+    /// Length of the deployed (runtime) code in bytes, what EIP-170 limits.
+    #[serde(rename = "deployedSize", default)]
+    pub deployed_size: usize,
+    /// The AST of the contract's source file. Kept out of the serialized
+    /// artifact so it doesn't bloat every `compile_success` payload.
+    #[serde(skip)]
+    pub ast: Option<Ast>,
+}
+
+impl ContractData {
+    /// The contract's own source file, relative to the root.
+    pub fn source_file(&self) -> Option<&str> {
+        let id = self.artifact.id? as usize;
+        self.source_list.get(id).map(String::as_str).filter(|f| !f.is_empty())
+    }
+
+    pub fn ethers_abi(&self) -> Option<ethers::abi::Abi> {
+        abi::from_json(self.artifact.abi.as_ref()?)
+    }
+}
+
+/// Which of the project's sources to compile, as globs relative to the root.
+#[derive(Default)]
+pub struct SourceFilter {
+    /// `--source`: only these files (and their imports). Empty means all.
+    pub include: Vec<glob::Pattern>,
+    /// `--ignore`: never these files, unless something else imports them.
+    pub exclude: Vec<glob::Pattern>,
+    /// `--watch`: more source directories besides the project's own.
+    pub extra_dirs: Vec<PathBuf>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.extra_dirs.is_empty()
+    }
+
+    /// Every `.sol` file under the project's source dir and `extra_dirs`.
+    pub fn files(&self, root: &Path) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::iter::once(project::source_dir(root))
+            .chain(self.extra_dirs.iter().cloned())
+            .flat_map(|dir| project::sol_files(&dir))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// `file` relative to the root, or to the `--watch` directory it's in
+    /// when that's outside the root, for matching `include`/`exclude`.
+    fn relative<'a>(&self, root: &Path, file: &'a Path) -> &'a Path {
+        std::iter::once(root)
+            .chain(self.extra_dirs.iter().map(PathBuf::as_path))
+            .find_map(|dir| file.strip_prefix(dir).ok())
+            .unwrap_or(file)
+    }
+
+    fn matches(&self, rel: &Path) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches_path(rel)))
+            && !self.exclude.iter().any(|p| p.matches_path(rel))
+    }
+}
+
+/// Which compiled contracts `compile_success` carries, as globs matched
+/// against each contract's source file relative to the root. The rest are
+/// still compiled and available through the per-contract endpoints.
+#[derive(Default)]
+pub struct ContractFilter {
+    /// `--contracts-include`: only contracts from these files. Empty means all.
+    pub include: Vec<glob::Pattern>,
+    /// `--contracts-exclude`: never contracts from these files, e.g. `lib/**`.
+    pub exclude: Vec<glob::Pattern>,
+}
+
+impl ContractFilter {
+    /// Contracts without a known source file are always shown.
+    pub fn shows(&self, contract: &ContractData) -> bool {
+        let Some(file) = contract.source_file() else { return true };
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(file)))
+            && !self.exclude.iter().any(|p| p.matches(file))
+    }
+}
+
+/// Outcome of the most recent compile, shared by the watcher and handlers.
+pub struct CompileState {
+    tx: broadcast::Sender<Broadcast>,
+    source_filter: SourceFilter,
+    contract_filter: ContractFilter,
+    /// `--size-limit`: deployed code size above which a contract is flagged.
+    size_limit: usize,
+    /// `--solc`: compile everything with this version instead of per-pragma.
+    solc_version: Option<semver::Version>,
+    /// `--via-ir`/`via_ir` from chasm.toml; `None` defers to foundry.toml.
+    via_ir: Option<bool>,
+    metrics: Arc<Metrics>,
+    /// Bumped by every compile that starts; only the newest may publish.
+    generation: AtomicU64,
+    /// Last compile message, replayed to newly connected clients.
+    pub last_msg: Mutex<Option<String>>,
+    /// Artifacts from the last successful compile.
+    pub contracts: RwLock<Vec<ContractData>>,
+    /// Artifacts from the successful compile before that, for `/compile/diff`.
+    pub previous: RwLock<Vec<ContractData>>,
+    /// Parsed deployed source maps, built on first use per contract.
+    source_maps: Mutex<HashMap<String, Arc<ContractMap>>>,
+}
+
+impl CompileState {
+    pub fn new(
+        tx: broadcast::Sender<Broadcast>,
+        source_filter: SourceFilter,
+        contract_filter: ContractFilter,
+        size_limit: usize,
+        solc_version: Option<semver::Version>,
+        via_ir: Option<bool>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            tx,
+            source_filter,
+            contract_filter,
+            size_limit,
+            solc_version,
+            via_ir,
+            metrics,
+            generation: AtomicU64::new(0),
+            last_msg: Mutex::new(None),
+            contracts: RwLock::new(Vec::new()),
+            previous: RwLock::new(Vec::new()),
+            source_maps: Mutex::new(HashMap::new()),
         }
+    }
 
-        let contracts_data: Vec<ContractData> = artifacts.into_iter().map(|(name, artifact)| {
-             ContractData { name, artifact }
-        }).collect();
+    /// A compiler for `root` using the pinned solc, if any. The pinned
+    /// version lives in svm's per-version directory, installed on first use,
+    /// so no global `svm use` default is touched. Nothing about the version
+    /// is cached here either: svm locks each version's install, so compiles
+    /// running at once (the watcher's, a `recompile`, `/compile/inline`)
+    /// can resolve the same or different versions without interfering.
+    pub fn compiler(&self, root: PathBuf) -> Result<Compiler> {
+        let solc = self.solc_version.as_ref().map(Solc::find_or_install).transpose()?;
+        let via_ir = self.via_ir(&root);
+        Compiler::new(root, solc, &self.source_filter.extra_dirs, via_ir)
+    }
 
-        let msg = CompileSuccess {
-             r#type: "compile_success".to_string(),
-             contracts: contracts_data,
-        };
+    /// Whether `root` compiles via IR. foundry.toml is read each time, so
+    /// editing it takes effect on the recompile that follows.
+    pub fn via_ir(&self, root: &Path) -> bool {
+        self.via_ir.or_else(|| project::foundry_via_ir(root)).unwrap_or(false)
+    }
+
+    /// The `--solc` version, if one is pinned.
+    pub fn solc_version(&self) -> Option<&semver::Version> {
+        self.solc_version.as_ref()
+    }
+
+    /// Every `.sol` file compiles look at: the project's source dir and the
+    /// `--watch` directories.
+    pub fn source_files(&self, root: &Path) -> Vec<PathBuf> {
+        self.source_filter.files(root)
+    }
+
+    /// The `--watch` directories, for the watcher.
+    pub fn extra_dirs(&self) -> &[PathBuf] {
+        &self.source_filter.extra_dirs
+    }
+
+    /// The sources `source_filter` selects, or `None` for the whole project.
+    fn selection(&self, root: &Path) -> Result<Option<Vec<PathBuf>>> {
+        if self.source_filter.is_empty() {
+            return Ok(None);
+        }
+        let files = selected_sources(root, &self.source_filter);
+        if files.is_empty() {
+            return Err(anyhow::anyhow!("No sources match the --source/--ignore filters"));
+        }
+        Ok(Some(files))
+    }
+
+    /// Compiles the whole project at `root` with `compiler`, or just the
+    /// sources matching `source_filter` and their imports.
+    fn compile(&self, compiler: &Compiler, root: &Path) -> Result<Vec<ContractData>> {
+        match self.selection(root)? {
+            None => compiler.compile(),
+            Some(files) => {
+                tracing::info!("Compiling {} selected sources", files.len());
+                compiler.compile_files(files)
+            }
+        }
+    }
+
+    /// Compiles what `compile_and_publish` would and returns the input of
+    /// the solc run that produced `contract`, or `None` if no run matches
+    /// (e.g. the sources changed since it was compiled).
+    pub fn solc_input(&self, root: PathBuf, contract: &ContractData) -> Result<Option<SolcInput>> {
+        let file = contract.source_file().ok_or_else(|| anyhow::anyhow!("{} has no source file", contract.name))?;
+        let version = contract.solc_version.as_deref().and_then(|v| semver::Version::parse(v).ok());
+        let files = self.selection(&root)?;
+        let runs = self.compiler(root)?.solc_inputs(files)?;
+        // A file imported by sources needing different solc versions is in
+        // several runs, so the version has to match too
+        Ok(runs.into_iter().find(|run| {
+            let has_file = run.input.get("sources").is_some_and(|sources| sources.get(file).is_some());
+            let same_version = version.as_ref().is_none_or(|v| run.solc_version == format!("{}.{}.{}", v.major, v.minor, v.patch));
+            has_file && same_version
+        }))
+    }
+
+    /// Compiles what `compile_and_publish` would, returning just the
+    /// diagnostics. Nothing is cached or broadcast.
+    pub fn check(&self, root: PathBuf) -> Result<Vec<Diagnostic>> {
+        let files = self.selection(&root)?;
+        self.compiler(root)?.diagnostics(files)
+    }
+
+    /// Compiles the project at `root`, caches the result and broadcasts it.
+    /// A failed compile only replaces the cached message when there are no
+    /// earlier artifacts, so new clients still see the last good ones.
+    /// Without any `.sol` files a `no_sources` message is sent instead; the
+    /// watcher compiles again once one is created. Otherwise a `compiling`
+    /// message naming the `changed` files that prompted it goes out first.
+    pub fn compile_and_publish(&self, root: PathBuf, changed: &[PathBuf]) {
+        // Claimed before anything is published, so a compile still running
+        // is stale even when this one only finds there's nothing to compile
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let src = project::source_dir(&root);
+        if self.source_filter.files(&root).is_empty() {
+            tracing::info!("No .sol files under {}, waiting for one to be created", src.display());
+            let json = ws::broadcast(&self.tx, &WsMessage::NoSources { dir: src, watch_dirs: self.source_filter.extra_dirs.clone() });
+            self.set_contracts(json, Vec::new());
+            return;
+        }
+        let files = changed.iter()
+            .map(|path| path.strip_prefix(&root).unwrap_or(path).to_string_lossy().to_string())
+            .collect();
+        self.broadcast(&WsMessage::Compiling { files });
 
-        Ok(serde_json::to_string(&msg)?)
+        // Keyed on the sources as they were before compiling, so an edit
+        // racing the compile invalidates the entry rather than being masked
+        let via_ir = self.via_ir(&root);
+        let key = cache::key(&root, &self.source_filter, self.solc_version.as_ref(), via_ir);
+        let started = Instant::now();
+        let result = self.compiler(root.clone())
+            .and_then(|compiler| Ok((self.compile(&compiler, &root)?, compiler.settings())));
+        let elapsed = started.elapsed();
+        self.metrics.record_compile(elapsed, result.is_ok());
+        // foundry-compilers can't be interrupted, so a compile overtaken by
+        // a newer one runs to completion and is then dropped, keeping clients
+        // from seeing its stale result after (or instead of) the fresh one
+        if self.generation.load(Ordering::SeqCst) != generation {
+            tracing::info!("Discarding compile result superseded by a newer compile after {:?}", elapsed);
+            if let Ok((contracts, _)) = &result {
+                cache::store(&root, &key, contracts);
+            }
+            return;
+        }
+        match result {
+            Ok((contracts, settings)) => {
+                cache::store(&root, &key, &contracts);
+                let json = ws::broadcast(&self.tx, &self.success_message(&contracts, elapsed, false, settings));
+                tracing::info!("Compilation successful in {:?}. Payload size: {}", elapsed, json.len());
+                self.set_contracts(json, contracts);
+            }
+            Err(e) => {
+                tracing::error!("Compilation failed: {}", e);
+                let json = ws::broadcast(&self.tx, &WsMessage::CompileError { error: e.to_string() });
+                let mut last_msg = self.last_msg.lock().unwrap();
+                if last_msg.is_none() || self.contracts.read().unwrap().is_empty() {
+                    *last_msg = Some(json);
+                }
+            }
+        }
+    }
+
+    /// Publishes the on-disk artifacts for `root` if its sources haven't
+    /// changed since they were cached. Returns whether there was a hit.
+    pub fn load_cached(&self, root: &Path) -> bool {
+        let started = Instant::now();
+        let via_ir = self.via_ir(root);
+        let Some(contracts) = cache::load(root, &cache::key(root, &self.source_filter, self.solc_version.as_ref(), via_ir)) else { return false };
+        // What a compile now would be given, which the cache key vouches for
+        let Ok(compiler) = self.compiler(root.to_path_buf()) else { return false };
+        let json = ws::broadcast(&self.tx, &self.success_message(&contracts, started.elapsed(), true, compiler.settings()));
+        self.set_contracts(json, contracts);
+        true
+    }
+
+    fn success_message(&self, contracts: &[ContractData], duration: Duration, cached: bool, settings: CompileSettings) -> WsMessage {
+        let msg = WsMessage::compile_success(contracts, &self.contract_filter, self.size_limit, duration, cached, settings);
+        if let WsMessage::CompileSuccess { size_warnings, .. } = &msg {
+            for warning in size_warnings {
+                tracing::warn!("{} is {} bytes deployed, over the {} byte limit", warning.contract, warning.deployed_size, warning.limit);
+            }
+        }
+        msg
+    }
+
+    /// Sends `msg` to clients without touching the cached compile message.
+    pub fn broadcast(&self, msg: &WsMessage) {
+        ws::broadcast(&self.tx, msg);
+    }
+
+    fn set_contracts(&self, json: String, contracts: Vec<ContractData>) {
+        *self.last_msg.lock().unwrap() = Some(json);
+        let replaced = std::mem::replace(&mut *self.contracts.write().unwrap(), contracts);
+        if !replaced.is_empty() {
+            *self.previous.write().unwrap() = replaced;
+        }
+        self.source_maps.lock().unwrap().clear();
+    }
+
+    /// The deployed source map of `name`, parsed once per compile.
+    pub fn source_map(&self, name: &str) -> Option<Arc<ContractMap>> {
+        if let Some(map) = self.source_maps.lock().unwrap().get(name) {
+            return Some(map.clone());
+        }
+        let map = Arc::new(ContractMap::from_contract(&self.contract(name)?)?);
+        self.source_maps.lock().unwrap().insert(name.to_string(), map.clone());
+        Some(map)
+    }
+
+    /// `(name, abi)` for every contract from the last successful compile.
+    pub fn abis(&self) -> Vec<(String, ethers::abi::Abi)> {
+        self.contracts.read().unwrap().iter()
+            .filter_map(|c| Some((c.name.clone(), c.ethers_abi()?)))
+            .collect()
+    }
+
+    /// Looks up a contract from the last successful compile by name.
+    pub fn contract(&self, name: &str) -> Option<ContractData> {
+        self.contracts.read().unwrap().iter().find(|c| c.name == name).cloned()
+    }
+}
+
+/// Source files whose path relative to `root` passes `filter`.
+fn selected_sources(root: &Path, filter: &SourceFilter) -> Vec<PathBuf> {
+    filter.files(root).into_iter()
+        .filter(|file| filter.matches(filter.relative(root, file)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A throwaway project whose only source pins `version` exactly.
+    fn project_with_pragma(name: &str, version: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("chasm-test-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let source = format!("// SPDX-License-Identifier: MIT\npragma solidity {};\n\ncontract {} {{}}\n", version, name);
+        std::fs::write(root.join("src").join(format!("{}.sol", name)), source).unwrap();
+        root
+    }
+
+    #[test]
+    fn concurrent_compiles_use_their_own_solc() {
+        let versions = [semver::Version::new(0, 8, 19), semver::Version::new(0, 7, 6)];
+        if let Some(e) = versions.iter().find_map(|v| Solc::find_or_install(v).err()) {
+            eprintln!("solc unavailable ({}), skipping", e);
+            return;
+        }
+        let state = CompileState::new(
+            broadcast::channel(16).0,
+            SourceFilter::default(),
+            ContractFilter::default(),
+            EIP170_SIZE_LIMIT,
+            None,
+            None,
+            Arc::new(Metrics::default()),
+        );
+        let projects = [("Newer", &versions[0]), ("Older", &versions[1])]
+            .map(|(name, version)| (name, version, project_with_pragma(name, &version.to_string())));
+
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = projects.iter()
+                .map(|(_, _, root)| scope.spawn(|| state.compile(&state.compiler(root.clone())?, root)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for ((name, version, root), result) in projects.iter().zip(results) {
+            let contracts = result.unwrap();
+            let contract = contracts.iter().find(|c| c.name == *name).unwrap();
+            let solc = contract.solc_version.as_deref().unwrap();
+            assert!(solc.starts_with(&version.to_string()), "{} compiled with {}, not {}", name, solc, version);
+            let _ = std::fs::remove_dir_all(root);
+        }
     }
 }
@@ -0,0 +1,105 @@
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-project config file, looked up in the project root.
+pub const PROJECT_CONFIG: &str = "chasm.toml";
+
+/// Defaults read from `chasm.toml`. Every field is optional: command-line
+/// flags override these, and these override the built-in defaults.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub host: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub anvil_port: Option<u16>,
+    pub fork_port: Option<u16>,
+    pub anvil_stop_grace: Option<f64>,
+    pub anvil_silent: Option<bool>,
+    pub anvil_verbosity: Option<u8>,
+    /// Compile every source with this solc version, e.g. `"0.8.20"`.
+    pub solc: Option<String>,
+    pub via_ir: Option<bool>,
+    /// Node used by endpoints when a request doesn't name an `rpcUrl`.
+    pub rpc_url: Option<String>,
+    pub sources: Option<Vec<String>>,
+    pub ignore: Option<Vec<String>>,
+    /// More source directories, relative to the project root.
+    pub watch: Option<Vec<PathBuf>>,
+    pub contracts_include: Option<Vec<String>>,
+    pub contracts_exclude: Option<Vec<String>>,
+    pub size_limit: Option<usize>,
+    pub max_concurrent_commands: Option<usize>,
+    pub ws_queue_size: Option<usize>,
+    pub cors_origins: Option<Vec<String>>,
+    pub allow_non_sol_writes: Option<bool>,
+    pub log_format: Option<crate::LogFormat>,
+    /// Load the project's `.env` at startup (default true).
+    pub dotenv: Option<bool>,
+    /// Serve the UI from this directory, relative to the project root.
+    pub ui_dir: Option<PathBuf>,
+}
+
+impl FileConfig {
+    /// Field-wise merge where `self` wins over `fallback`.
+    fn or(self, fallback: FileConfig) -> FileConfig {
+        FileConfig {
+            host: self.host.or(fallback.host),
+            port: self.port.or(fallback.port),
+            anvil_port: self.anvil_port.or(fallback.anvil_port),
+            fork_port: self.fork_port.or(fallback.fork_port),
+            anvil_stop_grace: self.anvil_stop_grace.or(fallback.anvil_stop_grace),
+            anvil_silent: self.anvil_silent.or(fallback.anvil_silent),
+            anvil_verbosity: self.anvil_verbosity.or(fallback.anvil_verbosity),
+            solc: self.solc.or(fallback.solc),
+            via_ir: self.via_ir.or(fallback.via_ir),
+            rpc_url: self.rpc_url.or(fallback.rpc_url),
+            sources: self.sources.or(fallback.sources),
+            ignore: self.ignore.or(fallback.ignore),
+            watch: self.watch.or(fallback.watch),
+            contracts_include: self.contracts_include.or(fallback.contracts_include),
+            contracts_exclude: self.contracts_exclude.or(fallback.contracts_exclude),
+            size_limit: self.size_limit.or(fallback.size_limit),
+            max_concurrent_commands: self.max_concurrent_commands.or(fallback.max_concurrent_commands),
+            ws_queue_size: self.ws_queue_size.or(fallback.ws_queue_size),
+            cors_origins: self.cors_origins.or(fallback.cors_origins),
+            allow_non_sol_writes: self.allow_non_sol_writes.or(fallback.allow_non_sol_writes),
+            log_format: self.log_format.or(fallback.log_format),
+            dotenv: self.dotenv.or(fallback.dotenv),
+            ui_dir: self.ui_dir.or(fallback.ui_dir),
+        }
+    }
+}
+
+/// The config files `load` reads that exist, lowest precedence first.
+pub fn existing_files(root: &Path) -> Vec<PathBuf> {
+    user_config_path().into_iter()
+        .chain(std::iter::once(root.join(PROJECT_CONFIG)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// `$XDG_CONFIG_HOME/chasm/config.toml`, falling back to `~/.config`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("chasm").join("config.toml"))
+}
+
+fn read(path: &Path) -> Result<FileConfig, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("Invalid {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FileConfig::default()),
+        Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// The user-wide config overlaid with the project's `chasm.toml`. Missing
+/// files are treated as empty; malformed ones are an error.
+pub fn load(root: &Path) -> Result<FileConfig, String> {
+    let user = match user_config_path() {
+        Some(path) => read(&path)?,
+        None => FileConfig::default(),
+    };
+    Ok(read(&root.join(PROJECT_CONFIG))?.or(user))
+}
@@ -0,0 +1,100 @@
+use serde::Serialize;
+
+/// The address forge-std's `console` and `console2` libraries call.
+const CONSOLE_ADDRESS: &str = "0x000000000000000000636F6e736F6c652e6c6f67";
+
+/// One `console.log` call found in a trace.
+#[derive(Serialize)]
+pub struct ConsoleLog {
+    /// The arguments joined by spaces, strings unquoted, as forge prints them.
+    pub message: String,
+    /// Each argument as it appears in the trace.
+    pub args: Vec<String>,
+}
+
+/// Every `console.log`/`console2.log` call in `cast run` output, in trace
+/// order. Calls show up decoded (`console::log("x", 1)`), or as a call to
+/// the console address when cast couldn't decode them.
+pub fn logs(output: &str) -> Vec<ConsoleLog> {
+    strip_ansi(output).lines().filter_map(|line| {
+        let call = ["console::log", "console2::log", CONSOLE_ADDRESS]
+            .iter()
+            .find_map(|needle| line.find(needle).map(|at| &line[at + needle.len()..]))?;
+        let args = split_args(call_args(call)?);
+        let message = args.iter().map(|arg| unquote(arg)).collect::<Vec<_>>().join(" ");
+        Some(ConsoleLog { message, args })
+    }).collect()
+}
+
+/// Removes the color and style escapes cast adds to its output.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with the first letter
+            if chars.next() == Some('[') {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// The text between the parentheses following the method name, e.g.
+/// `"x", 1` from `Uint("x", 1) [staticcall]`.
+fn call_args(rest: &str) -> Option<&str> {
+    let open = rest.find('(')?;
+    let mut depth = 0;
+    let mut quoted = false;
+    for (i, c) in rest.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&rest[open + 1..i]);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Splits on the commas that aren't inside a string or nested value.
+fn split_args(args: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' | '[' if !quoted => depth += 1,
+            ')' | ']' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+fn unquote(arg: &str) -> &str {
+    arg.strip_prefix('"').and_then(|a| a.strip_suffix('"')).unwrap_or(arg)
+}
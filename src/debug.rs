@@ -0,0 +1,441 @@
+use foundry_compilers::artifacts::BytecodeObject;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::compiler::ContractData;
+use crate::error::ApiError;
+use crate::locals;
+use crate::rpc;
+
+/// One decompressed solc source map entry (`s:l:f:j`; the modifier depth is
+/// not needed here).
+#[derive(Clone, Copy, Debug)]
+pub struct SourceMapEntry {
+    pub start: i64,
+    pub length: i64,
+    pub file: i64,
+    pub jump: char,
+}
+
+/// Expands solc's compressed source map, where empty fields repeat the
+/// previous entry's value.
+pub fn parse_source_map(map: &str) -> Vec<SourceMapEntry> {
+    let mut entries = Vec::new();
+    let mut last = SourceMapEntry { start: -1, length: -1, file: -1, jump: '-' };
+    for item in map.split(';') {
+        let fields: Vec<&str> = item.split(':').collect();
+        let field = |i: usize, current: i64| fields.get(i)
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(current);
+        last.start = field(0, last.start);
+        last.length = field(1, last.length);
+        last.file = field(2, last.file);
+        if let Some(jump) = fields.get(3).and_then(|j| j.chars().next()) {
+            last.jump = jump;
+        }
+        entries.push(last);
+    }
+    entries
+}
+
+/// Bytecode as raw bytes, with unlinked library placeholders zeroed since
+/// only instruction boundaries matter here.
+fn code_bytes(object: &BytecodeObject) -> Option<Vec<u8>> {
+    match object {
+        BytecodeObject::Bytecode(bytes) => Some(bytes.to_vec()),
+        BytecodeObject::Unlinked(hex) => {
+            let hex = hex.trim_start_matches("0x");
+            let mut cleaned = String::with_capacity(hex.len());
+            let mut i = 0;
+            while i < hex.len() {
+                if hex[i..].starts_with("__") {
+                    cleaned.push_str(&"0".repeat(40));
+                    i += 40;
+                } else {
+                    cleaned.push_str(hex.get(i..i + 2)?);
+                    i += 2;
+                }
+            }
+            ethers::utils::hex::decode(cleaned).ok()
+        }
+    }
+}
+
+/// Length of `code` without the trailing CBOR metadata, whose size is
+/// encoded in the last two bytes.
+fn code_len(code: &[u8]) -> usize {
+    let Some(tail) = code.len().checked_sub(2).and_then(|i| code.get(i..)) else { return code.len() };
+    let metadata_len = u16::from_be_bytes([tail[0], tail[1]]) as usize + 2;
+    code.len().checked_sub(metadata_len).unwrap_or(code.len())
+}
+
+/// Maps each PC that starts an instruction to the instruction's index,
+/// which is what source map entries are indexed by.
+fn instruction_indices(code: &[u8]) -> HashMap<usize, usize> {
+    let mut indices = HashMap::new();
+    let (mut pc, mut index) = (0, 0);
+    while pc < code.len() {
+        indices.insert(pc, index);
+        let op = code[pc];
+        // PUSH1..PUSH32 carry their operand inline
+        let operand = if (0x60..=0x7f).contains(&op) { (op - 0x5f) as usize } else { 0 };
+        pc += 1 + operand;
+        index += 1;
+    }
+    indices
+}
+
+/// A contract's parsed deployed source map, ready for PC lookups.
+pub struct ContractMap {
+    instructions: HashMap<usize, usize>,
+    entries: Vec<SourceMapEntry>,
+    source_list: Vec<String>,
+}
+
+impl ContractMap {
+    pub fn from_contract(contract: &ContractData) -> Option<Self> {
+        let bytecode = contract.artifact.deployed_bytecode.as_ref()?.bytecode.as_ref()?;
+        let code = code_bytes(&bytecode.object)?;
+        Some(Self {
+            instructions: instruction_indices(&code[..code_len(&code)]),
+            entries: parse_source_map(bytecode.source_map.as_deref()?),
+            source_list: contract.source_list.clone(),
+        })
+    }
+
+    /// The source map entry for `pc`, or `None` for PCs inside push data or
+    /// metadata and for compiler-generated code without a source file.
+    pub fn entry(&self, pc: usize) -> Option<SourceMapEntry> {
+        let entry = *self.entries.get(*self.instructions.get(&pc)?)?;
+        (entry.file >= 0 && entry.start >= 0).then_some(entry)
+    }
+
+    pub fn file(&self, entry: &SourceMapEntry) -> Option<&str> {
+        let file = self.source_list.get(usize::try_from(entry.file).ok()?)?;
+        (!file.is_empty()).then_some(file.as_str())
+    }
+}
+
+/// A resolved source range with 1-based lines and columns.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceLocation {
+    pub file: String,
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+}
+
+/// Source files read on demand for turning byte offsets into lines.
+pub struct SourceFiles {
+    root: PathBuf,
+    files: HashMap<String, Option<String>>,
+}
+
+impl SourceFiles {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, files: HashMap::new() }
+    }
+
+    pub fn locate(&mut self, map: &ContractMap, pc: usize) -> Option<SourceLocation> {
+        let entry = map.entry(pc)?;
+        let file = map.file(&entry)?;
+        let root = &self.root;
+        let source = self.files.entry(file.to_string())
+            .or_insert_with(|| std::fs::read_to_string(root.join(file)).ok())
+            .as_deref()?;
+
+        let start = entry.start as usize;
+        let end = start + entry.length.max(0) as usize;
+        let (start_line, start_col) = line_col(source, start)?;
+        let (end_line, end_col) = line_col(source, end)?;
+        Some(SourceLocation { file: file.to_string(), start_line, start_col, end_line, end_col })
+    }
+}
+
+fn line_col(source: &str, offset: usize) -> Option<(usize, usize)> {
+    let before = source.get(..offset)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    Some((line, before[line_start..].chars().count() + 1))
+}
+
+/// One opcode step from geth's struct logger.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Step {
+    pub pc: usize,
+    pub op: String,
+    pub gas: u64,
+    pub gas_cost: u64,
+    pub depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of `debug_traceTransaction` with the default (struct) tracer.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepTrace {
+    pub gas: u64,
+    pub failed: bool,
+    #[serde(default)]
+    pub return_value: String,
+    #[serde(rename(deserialize = "structLogs"), alias = "steps")]
+    pub steps: Vec<Step>,
+}
+
+/// What the struct logger should capture per step. Everything is on by
+/// default; turning memory and stack off shrinks the payload considerably.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default)]
+pub struct StepOptions {
+    pub memory: bool,
+    pub stack: bool,
+    pub storage: bool,
+}
+
+impl Default for StepOptions {
+    fn default() -> Self {
+        Self { memory: true, stack: true, storage: true }
+    }
+}
+
+impl StepOptions {
+    /// Tracer config understood by both geth and anvil. Geth reads
+    /// `enableMemory`, older nodes `disableMemory`, so both are sent.
+    fn tracer_config(&self) -> serde_json::Value {
+        serde_json::json!({
+            "enableMemory": self.memory,
+            "disableMemory": !self.memory,
+            "disableStack": !self.stack,
+            "disableStorage": !self.storage,
+        })
+    }
+}
+
+pub async fn trace_steps(
+    client: &reqwest::Client,
+    url: &str,
+    tx_hash: &str,
+    options: StepOptions,
+) -> Result<StepTrace, ApiError> {
+    let params = serde_json::json!([tx_hash, options.tracer_config()]);
+    let result = rpc::call(client, url, "debug_traceTransaction", params).await?;
+    serde_json::from_value(result)
+        .map_err(|e| ApiError::Upstream(format!("Unexpected debug_traceTransaction response: {}", e)))
+}
+
+/// A stepping session over a recorded opcode trace. Source locations are
+/// resolved against `map`, the deployed code of the transaction's target,
+/// so only top-level (depth 1) steps map to Solidity lines.
+pub struct Session {
+    trace: StepTrace,
+    map: Option<Arc<ContractMap>>,
+    /// The contract's source file AST as JSON, with its path, for locals.
+    ast: Option<(serde_json::Value, String)>,
+    files: SourceFiles,
+    /// Index of the next step to execute; `trace.steps.len()` once finished.
+    position: usize,
+    breakpoints: Vec<Breakpoint>,
+    /// The breakpoint the last `resume` stopped on.
+    hit: Option<Breakpoint>,
+}
+
+/// An unconditional line breakpoint. `file` is project-relative, matching
+/// the paths in `sourceList`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Breakpoint {
+    pub file: String,
+    pub line: usize,
+}
+
+/// A call frame entered during the trace, outermost first.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    pub depth: usize,
+    /// Index of the CALL/CREATE step that entered this frame; `None` for the
+    /// transaction's own frame.
+    pub call_index: Option<usize>,
+    pub op: Option<String>,
+    /// Callee address, when the stack was captured and the op has one.
+    pub address: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionState<'a> {
+    pub id: u64,
+    pub index: usize,
+    pub total: usize,
+    pub done: bool,
+    pub step: Option<&'a Step>,
+    pub location: Option<SourceLocation>,
+    pub call_stack: Vec<Frame>,
+    pub breakpoint: Option<Breakpoint>,
+}
+
+impl Session {
+    pub fn new(trace: StepTrace, map: Option<Arc<ContractMap>>, contract: Option<&ContractData>, root: PathBuf) -> Self {
+        let ast = contract.and_then(|c| {
+            let ast = c.ast.as_ref()?;
+            Some((serde_json::to_value(ast).ok()?, ast.absolute_path.clone()))
+        });
+        Self {
+            trace,
+            map,
+            ast,
+            files: SourceFiles::new(root),
+            position: 0,
+            breakpoints: Vec::new(),
+            hit: None,
+        }
+    }
+
+    /// In-scope Solidity locals at the current step; see `locals::inspect`.
+    pub fn locals(&self) -> Result<locals::Locals, &'static str> {
+        let (Some(map), Some((ast, file))) = (&self.map, &self.ast) else {
+            return Err("The session was created without a contract, or its AST is unavailable");
+        };
+        locals::inspect(ast, file, map, &self.trace.steps, self.position)
+    }
+
+    fn location_at(&mut self, index: usize) -> Option<SourceLocation> {
+        let step = self.trace.steps.get(index)?;
+        if step.depth != 1 {
+            return None;
+        }
+        let map = self.map.clone()?;
+        self.files.locate(&map, step.pc)
+    }
+
+    /// Advances one opcode.
+    pub fn step_opcode(&mut self) {
+        self.position = (self.position + 1).min(self.trace.steps.len());
+    }
+
+    /// Advances until execution reaches a different source line than the
+    /// current one, skipping steps without a mapping.
+    pub fn step_line(&mut self) {
+        let line = |loc: &SourceLocation| (loc.file.clone(), loc.start_line);
+        let current = self.location_at(self.position).map(|loc| line(&loc));
+        loop {
+            self.step_opcode();
+            if self.position >= self.trace.steps.len() {
+                return;
+            }
+            if let Some(loc) = self.location_at(self.position) {
+                if Some(line(&loc)) != current {
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<Breakpoint>) {
+        self.breakpoints = breakpoints;
+    }
+
+    /// Runs until a step enters a line with a breakpoint, or to the end.
+    /// Steps that stay on the line execution started from don't stop, so
+    /// continuing from a breakpoint moves past it.
+    pub fn resume(&mut self) {
+        self.hit = None;
+        let mut last_line = self.location_at(self.position).map(|loc| (loc.file, loc.start_line));
+        while self.position < self.trace.steps.len() {
+            self.step_opcode();
+            let Some(loc) = self.location_at(self.position) else { continue };
+            let line = (loc.file, loc.start_line);
+            if last_line.as_ref() != Some(&line) {
+                let hit = self.breakpoints.iter().find(|bp| bp.file == line.0 && bp.line == line.1);
+                if let Some(hit) = hit {
+                    self.hit = Some(hit.clone());
+                    return;
+                }
+            }
+            last_line = Some(line);
+        }
+    }
+
+    /// Frames active at the current step, rebuilt from depth changes.
+    fn call_stack(&self) -> Vec<Frame> {
+        let mut frames = vec![Frame { depth: 1, call_index: None, op: None, address: None }];
+        let end = self.position.min(self.trace.steps.len());
+        for (index, step) in self.trace.steps[..end].iter().enumerate() {
+            let Some(next) = self.trace.steps.get(index + 1) else { break };
+            if next.depth > step.depth {
+                frames.push(Frame {
+                    depth: next.depth,
+                    call_index: Some(index),
+                    op: Some(step.op.clone()),
+                    address: call_target(step),
+                });
+            }
+            frames.retain(|f| f.depth <= next.depth);
+        }
+        frames
+    }
+
+    pub fn state(&mut self, id: u64) -> SessionState<'_> {
+        let location = self.location_at(self.position);
+        let total = self.trace.steps.len();
+        SessionState {
+            id,
+            index: self.position,
+            total,
+            done: self.position >= total,
+            step: self.trace.steps.get(self.position),
+            location,
+            call_stack: self.call_stack(),
+            breakpoint: self.hit.clone(),
+        }
+    }
+}
+
+/// The address a CALL-family op is about to call; the stack is listed
+/// bottom to top, so the address is the second item from the end.
+fn call_target(step: &Step) -> Option<String> {
+    if !matches!(step.op.as_str(), "CALL" | "CALLCODE" | "DELEGATECALL" | "STATICCALL") {
+        return None;
+    }
+    let stack = step.stack.as_ref()?;
+    let word = stack.get(stack.len().checked_sub(2)?)?.trim_start_matches("0x");
+    let padded = format!("{:0>40}", word);
+    Some(format!("0x{}", &padded[padded.len() - 40..]))
+}
+
+/// Open debug sessions, keyed by an increasing id.
+#[derive(Default)]
+pub struct Sessions {
+    next_id: u64,
+    sessions: HashMap<u64, Session>,
+}
+
+impl Sessions {
+    pub fn insert(&mut self, session: Session) -> u64 {
+        self.next_id += 1;
+        self.sessions.insert(self.next_id, session);
+        self.next_id
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Session> {
+        self.sessions.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut Session> {
+        self.sessions.get_mut(&id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        self.sessions.remove(&id).is_some()
+    }
+}
@@ -0,0 +1,96 @@
+use foundry_compilers::artifacts::{BytecodeObject, ConfigurableContractArtifact};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use crate::compiler::ContractData;
+use crate::storage;
+
+/// How one contract differs between two compiles.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractChange {
+    pub name: String,
+    pub file: Option<String>,
+    /// `added`, `removed`, `changed` or `unchanged`.
+    pub status: &'static str,
+    pub bytecode_changed: bool,
+    pub abi_changed: bool,
+    pub storage_layout_changed: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct Summary {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+}
+
+/// Compares every contract in `old` and `new`, matched by file and name.
+pub fn compare(old: &[ContractData], new: &[ContractData]) -> (Vec<ContractChange>, Summary) {
+    let key = |c: &ContractData| (c.source_file().unwrap_or_default().to_string(), c.name.clone());
+    let old: BTreeMap<_, _> = old.iter().map(|c| (key(c), c)).collect();
+    let new: BTreeMap<_, _> = new.iter().map(|c| (key(c), c)).collect();
+    let mut keys: Vec<_> = old.keys().chain(new.keys()).cloned().collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut summary = Summary::default();
+    let changes = keys.into_iter().map(|key| {
+        let (bytecode, abi, layout, status) = match (old.get(&key), new.get(&key)) {
+            (Some(before), Some(after)) => {
+                let (before, after) = (&before.artifact, &after.artifact);
+                let bytecode = code(before) != code(after);
+                let abi = before.abi != after.abi;
+                let layout = before.storage_layout.as_ref().map(storage::normalized)
+                    != after.storage_layout.as_ref().map(storage::normalized);
+                let status = if bytecode || abi || layout { "changed" } else { "unchanged" };
+                (bytecode, abi, layout, status)
+            }
+            (None, _) => (true, true, true, "added"),
+            (_, None) => (true, true, true, "removed"),
+        };
+        match status {
+            "added" => summary.added += 1,
+            "removed" => summary.removed += 1,
+            "changed" => summary.changed += 1,
+            _ => summary.unchanged += 1,
+        }
+        let (file, name) = key;
+        ContractChange {
+            name,
+            file: Some(file).filter(|f| !f.is_empty()),
+            status,
+            bytecode_changed: bytecode,
+            abi_changed: abi,
+            storage_layout_changed: layout,
+        }
+    }).collect();
+    (changes, summary)
+}
+
+/// Creation and runtime code as hex, without solc's trailing CBOR metadata,
+/// whose source hash changes on any edit (even to a comment).
+fn code(artifact: &ConfigurableContractArtifact) -> [Option<String>; 2] {
+    let creation = artifact.bytecode.as_ref().map(|b| &b.object);
+    let runtime = artifact.deployed_bytecode.as_ref().and_then(|d| d.bytecode.as_ref()).map(|b| &b.object);
+    [creation.map(without_metadata), runtime.map(without_metadata)]
+}
+
+pub fn without_metadata(object: &BytecodeObject) -> String {
+    hex_without_metadata(match object {
+        BytecodeObject::Bytecode(bytes) => ethers::utils::hex::encode(bytes),
+        BytecodeObject::Unlinked(s) => s.trim_start_matches("0x").to_string(),
+    })
+}
+
+/// `hex` (no `0x`) minus the trailing metadata, if its length suffix fits.
+pub fn hex_without_metadata(hex: String) -> String {
+    // The last two bytes are the big-endian length of the CBOR blob before them
+    let metadata_len = hex.len().checked_sub(4)
+        .and_then(|start| usize::from_str_radix(hex.get(start..)?, 16).ok())
+        .map(|len| (len + 2) * 2);
+    match metadata_len {
+        Some(len) if len <= hex.len() => hex[..hex.len() - len].to_string(),
+        _ => hex,
+    }
+}
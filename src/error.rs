@@ -0,0 +1,114 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Error returned by API handlers. Each variant maps to one status code and a
+/// stable `code` string so non-UI clients can branch on it.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The request itself was malformed or referenced something invalid.
+    BadRequest(String),
+    /// The request lacks a valid `--auth-token`.
+    Unauthorized(String),
+    /// The requested resource doesn't exist.
+    NotFound(String),
+    /// Too many external commands are already running.
+    TooManyRequests(String),
+    /// A required external binary (forge, cast, ...) isn't installed.
+    ToolMissing(String),
+    /// An external tool or RPC endpoint failed.
+    Upstream(String),
+    /// The call reverted; `revert` is the decoded revert data (see
+    /// `abi::decode_revert`).
+    Reverted { message: String, revert: serde_json::Value },
+    /// Anything else that went wrong on our side.
+    Internal(String),
+}
+
+pub type ApiResult<T> = Result<Json<T>, ApiError>;
+
+impl ApiError {
+    /// Classifies a failure to spawn `tool`, telling a missing binary (with
+    /// how to get it) apart from other I/O errors.
+    pub fn spawn_failed(tool: &str, e: std::io::Error) -> Self {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return ApiError::Internal(format!("Failed to execute {}: {}", tool, e));
+        }
+        if crate::toolchain::FOUNDRY_TOOLS.contains(&tool) {
+            ApiError::ToolMissing(format!("{} not found on PATH; install Foundry (https://getfoundry.sh)", tool))
+        } else {
+            ApiError::ToolMissing(format!("{} not found on PATH; install it to use this endpoint", tool))
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::ToolMissing(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ApiError::Reverted { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::ToolMissing(_) => "tool_missing",
+            ApiError::Upstream(_) => "upstream_error",
+            ApiError::Reverted { .. } => "reverted",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(msg)
+            | ApiError::Unauthorized(msg)
+            | ApiError::NotFound(msg)
+            | ApiError::TooManyRequests(msg)
+            | ApiError::ToolMissing(msg)
+            | ApiError::Upstream(msg)
+            | ApiError::Internal(msg)
+            | ApiError::Reverted { message: msg, .. } => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({
+            "error": self.message(),
+            "code": self.code(),
+        });
+        if let ApiError::Reverted { revert, .. } = &self {
+            body["revert"] = revert.clone();
+        }
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// Describes a command that ran but failed: `what`, how it exited and its
+/// stderr, e.g. `Forge failed (exit code 1): Error: ...`.
+pub fn command_failed(what: &str, output: &std::process::Output) -> String {
+    let status = match output.status.code() {
+        Some(code) => format!("exit code {}", code),
+        // Killed by a signal, on unix
+        None => output.status.to_string(),
+    };
+    format!("{} ({}): {}", what, status, String::from_utf8_lossy(&output.stderr).trim_end())
+}
@@ -0,0 +1,61 @@
+use crate::error::ApiError;
+
+/// Flags clients may pass through to `cast`, and whether each takes a value.
+/// Anything that would redirect where a command reads or writes (RPC URL,
+/// keys, output files) is left out on purpose.
+const ALLOWED: &[(&str, bool)] = &[
+    ("--chain", true),
+    ("--compute-units-per-second", true),
+    ("--decode-internal", false),
+    ("--evm-version", true),
+    ("--gas-limit", true),
+    ("--label", true),
+    ("--legacy", false),
+    ("--no-rate-limit", false),
+    ("--with-local-artifacts", false),
+];
+
+/// Only `cast run` takes these (`--quick` skips replaying the block's earlier
+/// transactions); `cast call` would fail on them.
+const RUN_ONLY: &[(&str, bool)] = &[
+    ("--quick", false),
+];
+
+/// The `cast` subcommand extra arguments are for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CastCommand {
+    Run,
+    Call,
+}
+
+/// Checks `args` against `ALLOWED`, plus `RUN_ONLY` for `cast run`. Values
+/// go either after `=` or as the next argument. Each argument is passed to
+/// the command on its own, so shell metacharacters in them have no effect
+/// either way.
+pub fn validate(args: &[String], command: CastCommand) -> Result<&[String], ApiError> {
+    let run_only = if command == CastCommand::Run { RUN_ONLY } else { &[] };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        let Some(&(_, takes_value)) = ALLOWED.iter().chain(run_only).find(|(name, _)| *name == flag) else {
+            return Err(ApiError::BadRequest(format!("Extra argument {} isn't allowed", arg)));
+        };
+        match (takes_value, inline_value) {
+            (false, Some(_)) => return Err(ApiError::BadRequest(format!("{} doesn't take a value", flag))),
+            (true, None) => match iter.next() {
+                Some(value) if !value.starts_with('-') => {}
+                _ => return Err(ApiError::BadRequest(format!("{} needs a value", flag))),
+            },
+            _ => {}
+        }
+    }
+    Ok(args)
+}
+
+/// Splits a whitespace-separated list from a query string.
+pub fn from_query(args: Option<&str>) -> Vec<String> {
+    args.unwrap_or_default().split_whitespace().map(str::to_string).collect()
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Per-project state directory, in the project root.
+pub const STATE_DIR: &str = ".chasm";
+const HISTORY_FILE: &str = "trace-history.json";
+/// Oldest entries are dropped past this many.
+pub const MAX_ENTRIES: usize = 100;
+
+/// One `/trace/:hash` request, enough to run it again.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    pub id: u64,
+    pub hash: String,
+    pub rpc_url: String,
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Unix seconds of the latest time it was traced.
+    pub timestamp: u64,
+}
+
+/// Traced transactions, newest first, kept in `.chasm/trace-history.json`
+/// so they survive restarts.
+pub struct TraceHistory {
+    path: PathBuf,
+    entries: Mutex<Vec<TraceEntry>>,
+}
+
+impl TraceHistory {
+    /// Reads the history of `root`. A missing or unreadable file starts empty.
+    pub fn load(root: &Path) -> Self {
+        let path = root.join(STATE_DIR).join(HISTORY_FILE);
+        let entries = std::fs::read(&path).ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Self { path, entries: Mutex::new(entries) }
+    }
+
+    pub fn list(&self) -> Vec<TraceEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, id: u64) -> Option<TraceEntry> {
+        self.entries.lock().unwrap().iter().find(|e| e.id == id).cloned()
+    }
+
+    /// Moves the trace to the front, adding it if it's new.
+    pub fn record(&self, hash: &str, rpc_url: &str, extra_args: &[String]) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut entries = self.entries.lock().unwrap();
+        let same = |e: &TraceEntry| e.hash == hash && e.rpc_url == rpc_url && e.extra_args == extra_args;
+        let entry = match entries.iter().position(same) {
+            Some(index) => TraceEntry { timestamp, ..entries.remove(index) },
+            None => TraceEntry {
+                id: entries.iter().map(|e| e.id).max().map_or(1, |id| id + 1),
+                hash: hash.to_string(),
+                rpc_url: rpc_url.to_string(),
+                extra_args: extra_args.to_vec(),
+                timestamp,
+            },
+        };
+        entries.insert(0, entry);
+        entries.truncate(MAX_ENTRIES);
+        self.save(&entries);
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        self.save(&entries);
+    }
+
+    fn save(&self, entries: &[TraceEntry]) {
+        let result = self.path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&self.path, serde_json::to_vec_pretty(entries)?));
+        if let Err(e) = result {
+            tracing::warn!("Failed to write trace history {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use foundry_compilers::artifacts::{Contract, Settings, SolcInput, SolcLanguage, Source, Sources};
+use foundry_compilers::multi::MultiCompilerError;
+use foundry_compilers::solc::Solc;
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::compiler::Diagnostic;
+
+/// One contract from a scratch compile, with solc's output for it as is.
+#[derive(Serialize)]
+pub struct InlineContract {
+    pub name: String,
+    pub file: String,
+    #[serde(flatten)]
+    pub output: Contract,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineOutput {
+    pub success: bool,
+    pub solc_version: String,
+    pub contracts: Vec<InlineContract>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// `pinned`, or else the newest solc every pragma in `sources` allows,
+/// installing it if needed. Sources without a pragma don't constrain it.
+fn solc_for(sources: &Sources, pinned: Option<&Version>) -> Result<Solc> {
+    if let Some(version) = pinned {
+        return Ok(Solc::find_or_install(version)?);
+    }
+    let comparators = sources.values()
+        .filter_map(|source| Solc::source_version_req(source).ok())
+        .flat_map(|req| req.comparators)
+        .collect();
+    let req = VersionReq { comparators };
+    let version = Solc::ensure_installed(&req)
+        .map_err(|e| anyhow::anyhow!("Failed to find or install a solc matching {}: {}", req, e))?;
+    Ok(Solc::find_or_install(&version)?)
+}
+
+/// Compiles `sources` (file name to content) through solc's standard JSON
+/// interface, without the project or anything else on disk. Imports must
+/// name one of the other sources. `settings` defaults to solc's own.
+pub fn compile(sources: BTreeMap<String, String>, settings: Option<Settings>, pinned: Option<&Version>) -> Result<InlineOutput> {
+    let sources = Sources(sources.into_iter().map(|(file, content)| (PathBuf::from(file), Source::new(content))).collect());
+    let solc = solc_for(&sources, pinned)?;
+    let input = SolcInput { language: SolcLanguage::Solidity, sources, settings: settings.unwrap_or_default() }
+        .sanitized(&solc.version);
+    let output = solc.compile_exact(&input)?;
+
+    let diagnostics: Vec<Diagnostic> = output.errors.into_iter()
+        .map(|e| Diagnostic::new(&MultiCompilerError::Solc(e), Path::new("")))
+        .collect();
+    let contracts = output.contracts.into_iter()
+        .flat_map(|(file, contracts)| {
+            let file = file.to_string_lossy().to_string();
+            contracts.into_iter().map(move |(name, output)| InlineContract { name, file: file.clone(), output })
+        })
+        .collect();
+    Ok(InlineOutput {
+        success: !diagnostics.iter().any(|d| d.severity == "error"),
+        solc_version: solc.version.to_string(),
+        contracts,
+        diagnostics,
+    })
+}
@@ -0,0 +1,101 @@
+use ethers::abi::{Abi, Function};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, TransactionRequest, U256};
+use ethers::utils::hex;
+use std::time::Duration;
+use crate::abi;
+use crate::error::ApiError;
+use crate::rpc;
+
+/// How long `send` waits for the transaction to be mined.
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(30);
+const RECEIPT_POLL: Duration = Duration::from_millis(500);
+
+/// Finds `function` in `abi`, by full signature (`transfer(address,uint256)`)
+/// or by name. An overloaded name picks the overload taking `arg_count`
+/// arguments, and is an error if that's still ambiguous.
+pub fn find_function<'a>(abi: &'a Abi, function: &str, arg_count: usize) -> Result<&'a Function, ApiError> {
+    if function.contains('(') {
+        let signature: String = function.chars().filter(|c| !c.is_whitespace()).collect();
+        return abi.functions().find(|f| abi::function_signature(f) == signature)
+            .ok_or_else(|| ApiError::NotFound(format!("No function {} in the ABI", function)));
+    }
+    let overloads = abi.functions_by_name(function)
+        .map_err(|_| ApiError::NotFound(format!("No function {} in the ABI", function)))?;
+    let mut matching = overloads.iter().filter(|f| overloads.len() == 1 || f.inputs.len() == arg_count);
+    match (matching.next(), matching.next()) {
+        (Some(f), None) => Ok(f),
+        (None, _) => Err(ApiError::BadRequest(format!("No overload of {} takes {} arguments", function, arg_count))),
+        (Some(_), Some(_)) => Err(ApiError::BadRequest(format!(
+            "{} is overloaded; give its full signature, one of: {}",
+            function,
+            overloads.iter().map(abi::function_signature).collect::<Vec<_>>().join(", "),
+        ))),
+    }
+}
+
+/// ABI-encodes a call to `function` with `args` given as JSON.
+pub fn encode(function: &Function, args: &[serde_json::Value]) -> Result<Vec<u8>, ApiError> {
+    if args.len() != function.inputs.len() {
+        return Err(ApiError::BadRequest(format!(
+            "{} takes {} arguments, got {}", abi::function_signature(function), function.inputs.len(), args.len(),
+        )));
+    }
+    let tokens = function.inputs.iter().zip(args)
+        .map(|(param, value)| abi::json_to_token(&param.kind, value)
+            .map_err(|e| ApiError::BadRequest(format!("Argument {}: {}", param.name, e))))
+        .collect::<Result<Vec<_>, _>>()?;
+    function.encode_input(&tokens).map_err(|e| ApiError::BadRequest(format!("Failed to encode arguments: {}", e)))
+}
+
+/// Signs a legacy transaction with `private_key` and sends it, filling in
+/// the chain id, nonce, gas and gas price from the node. Returns the hash
+/// and the receipt, which is `null` if it wasn't mined within the timeout.
+pub async fn send(
+    client: &reqwest::Client,
+    url: &str,
+    private_key: &str,
+    to: Address,
+    data: Vec<u8>,
+    value: U256,
+) -> Result<(String, serde_json::Value), ApiError> {
+    let wallet: LocalWallet = private_key.trim_start_matches("0x").parse()
+        .map_err(|_| ApiError::BadRequest("Invalid private key".to_string()))?;
+    let quantity = |value: serde_json::Value, what: &str| rpc::quantity(&value)
+        .ok_or_else(|| ApiError::Upstream(format!("Unexpected {} {}", what, value)));
+
+    let chain_id = quantity(rpc::call(client, url, "eth_chainId", serde_json::json!([])).await?, "eth_chainId")?;
+    let from = wallet.address();
+    let nonce = quantity(rpc::call(client, url, "eth_getTransactionCount", serde_json::json!([from, "pending"])).await?, "nonce")?;
+    let gas_price = quantity(rpc::call(client, url, "eth_gasPrice", serde_json::json!([])).await?, "gas price")?;
+    let data = Bytes::from(data);
+    let estimate = serde_json::json!([{ "from": from, "to": to, "data": data, "value": value }]);
+    let gas = quantity(rpc::call(client, url, "eth_estimateGas", estimate).await?, "gas estimate")?;
+
+    let tx: TypedTransaction = TransactionRequest::new()
+        .from(from)
+        .to(to)
+        .data(data)
+        .value(value)
+        .nonce(nonce)
+        .gas(gas)
+        .gas_price(gas_price)
+        .chain_id(chain_id.as_u64())
+        .into();
+    let signature = wallet.with_chain_id(chain_id.as_u64()).sign_transaction(&tx).await
+        .map_err(|e| ApiError::Internal(format!("Failed to sign transaction: {}", e)))?;
+    let raw = format!("0x{}", hex::encode(tx.rlp_signed(&signature)));
+    let hash = rpc::call(client, url, "eth_sendRawTransaction", serde_json::json!([raw])).await?;
+    let hash = hash.as_str().map(str::to_string)
+        .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_sendRawTransaction result {}", hash)))?;
+
+    let deadline = tokio::time::Instant::now() + RECEIPT_TIMEOUT;
+    loop {
+        let receipt = rpc::call(client, url, "eth_getTransactionReceipt", serde_json::json!([hash])).await?;
+        if !receipt.is_null() || tokio::time::Instant::now() >= deadline {
+            return Ok((hash, receipt));
+        }
+        tokio::time::sleep(RECEIPT_POLL).await;
+    }
+}
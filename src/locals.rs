@@ -0,0 +1,181 @@
+use ethers::types::U256;
+use ethers::utils::hex;
+use serde::Serialize;
+use serde_json::Value;
+use crate::abi;
+use crate::debug::{ContractMap, Step};
+
+/// A variable in scope at the current step. `value` is `None` when it
+/// couldn't be decoded, with `reason` saying why.
+#[derive(Serialize)]
+pub struct Local {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub kind: &'static str,
+    pub value: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+pub struct Locals {
+    pub function: String,
+    pub locals: Vec<Local>,
+}
+
+/// Byte range of an AST node from its `src` (`start:length:file`).
+fn range(node: &Value) -> Option<(usize, usize)> {
+    let mut parts = node.get("src")?.as_str()?.split(':');
+    let start = parts.next()?.parse().ok()?;
+    let length: usize = parts.next()?.parse().ok()?;
+    Some((start, start + length))
+}
+
+fn contains(node: &Value, offset: usize) -> bool {
+    range(node).is_some_and(|(start, end)| start <= offset && offset < end)
+}
+
+fn node_type(node: &Value) -> &str {
+    node.get("nodeType").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Child nodes of any kind: every object or array-of-objects field.
+fn children(node: &Value) -> Vec<&Value> {
+    let Some(object) = node.as_object() else { return Vec::new() };
+    object.values().flat_map(|v| match v {
+        Value::Array(items) => items.iter().filter(|i| i.is_object()).collect(),
+        Value::Object(_) => vec![v],
+        _ => Vec::new(),
+    }).collect()
+}
+
+/// The innermost function or modifier whose body contains `offset`.
+fn enclosing_function(node: &Value, offset: usize) -> Option<&Value> {
+    for child in children(node).into_iter().filter(|c| contains(c, offset)) {
+        if let Some(found) = enclosing_function(child, offset) {
+            return Some(found);
+        }
+        if matches!(node_type(child), "FunctionDefinition" | "ModifierDefinition") {
+            return Some(child);
+        }
+    }
+    None
+}
+
+/// Local declarations still in scope at `offset`: those made earlier in
+/// every block (or `for` header) enclosing it, outermost first.
+fn scoped_declarations<'a>(node: &'a Value, offset: usize, out: &mut Vec<&'a Value>) {
+    let statements: Vec<&Value> = match node_type(node) {
+        "Block" | "UncheckedBlock" => node.get("statements").and_then(Value::as_array)
+            .map(|s| s.iter().collect())
+            .unwrap_or_default(),
+        "ForStatement" => node.get("initializationExpression").into_iter().collect(),
+        _ => Vec::new(),
+    };
+    for statement in statements {
+        let before = range(statement).is_some_and(|(_, end)| end <= offset);
+        if before && node_type(statement) == "VariableDeclarationStatement" {
+            let declarations = statement.get("declarations").and_then(Value::as_array);
+            out.extend(declarations.into_iter().flatten().filter(|d| d.is_object()));
+        }
+    }
+    for child in children(node).into_iter().filter(|c| contains(c, offset)) {
+        scoped_declarations(child, offset, out);
+    }
+}
+
+fn parameters<'a>(function: &'a Value, field: &str) -> Vec<&'a Value> {
+    function.get(field)
+        .and_then(|list| list.get("parameters"))
+        .and_then(Value::as_array)
+        .map(|params| params.iter().collect())
+        .unwrap_or_default()
+}
+
+/// Best-effort decoding of in-scope locals at `steps[position]`, assuming
+/// the legacy (non-IR) code generator's stack layout: parameters sit on top
+/// of the stack when the function's entry JUMPDEST runs, with return
+/// variables and then locals pushed above them in declaration order.
+pub fn inspect(ast: &Value, source_file: &str, map: &ContractMap, steps: &[Step], position: usize) -> Result<Locals, &'static str> {
+    let step = steps.get(position).ok_or("The session has finished")?;
+    let stack = step.stack.as_ref().ok_or("The trace was captured without the stack")?;
+    let entry = map.entry(step.pc).ok_or("The current step has no source mapping")?;
+    if map.file(&entry) != Some(source_file) {
+        return Err("The current step is outside the contract's source file");
+    }
+    let offset = entry.start as usize;
+    let function = enclosing_function(ast, offset).ok_or("The current step isn't inside a function")?;
+
+    // Find the frame's entry: the latest JUMPDEST at this depth mapped to
+    // the whole function
+    let function_range = range(function);
+    let entry_height = steps[..=position].iter().rev()
+        .take_while(|s| s.depth >= step.depth)
+        .filter(|s| s.depth == step.depth && s.op == "JUMPDEST")
+        .find(|s| map.entry(s.pc).is_some_and(|e| {
+            Some((e.start as usize, (e.start + e.length) as usize)) == function_range
+        }))
+        .and_then(|s| s.stack.as_ref().map(Vec::len));
+
+    let params = parameters(function, "parameters");
+    let returns = parameters(function, "returnParameters");
+    let mut body_locals = Vec::new();
+    if let Some(body) = function.get("body") {
+        scoped_declarations(body, offset, &mut body_locals);
+    }
+
+    let mut locals = Vec::new();
+    let mut slot = |decl: &Value, kind: &'static str, index: Option<usize>| {
+        let ty = decl.pointer("/typeDescriptions/typeString").and_then(Value::as_str).unwrap_or_default();
+        let (value, reason) = match (entry_height, index) {
+            (None, _) | (_, None) => (None, Some("Couldn't find the function's entry in the trace")),
+            (Some(_), Some(i)) => match stack.get(i) {
+                None => (None, Some("Not on the stack yet")),
+                Some(word) => match decode(ty, word, step.memory.as_deref()) {
+                    Some(value) => (Some(value), None),
+                    None => (None, Some("Only value types and memory strings/bytes are decoded")),
+                },
+            },
+        };
+        locals.push(Local {
+            name: decl.get("name").and_then(Value::as_str).unwrap_or_default().to_string(),
+            ty: ty.to_string(),
+            kind,
+            value,
+            reason,
+        });
+    };
+    for (i, decl) in params.iter().enumerate() {
+        let index = entry_height.and_then(|h| (h + i).checked_sub(params.len()));
+        slot(decl, "parameter", index);
+    }
+    for (i, decl) in returns.iter().chain(body_locals.iter()).enumerate() {
+        let kind = if i < returns.len() { "return" } else { "local" };
+        slot(decl, kind, entry_height.map(|h| h + i));
+    }
+
+    Ok(Locals {
+        function: function.get("name").and_then(Value::as_str).unwrap_or_default().to_string(),
+        locals,
+    })
+}
+
+/// Decodes a stack word of Solidity type `ty`. Memory `string`/`bytes` are
+/// followed into `memory` (32-byte hex words) when it was captured.
+fn decode(ty: &str, word: &str, memory: Option<&[String]>) -> Option<Value> {
+    let word = U256::from_str_radix(word.trim_start_matches("0x"), 16).ok()?;
+    if ty == "string memory" || ty == "bytes memory" {
+        let words: Vec<&str> = memory?.iter().map(|w| w.trim_start_matches("0x")).collect();
+        let memory = hex::decode(words.concat()).ok()?;
+        let ptr = usize::try_from(word).ok()?;
+        let len = usize::try_from(U256::from_big_endian(memory.get(ptr..ptr.checked_add(32)?)?)).ok()?;
+        let data = memory.get(ptr + 32..(ptr + 32).checked_add(len)?)?;
+        return Some(if ty == "string memory" {
+            Value::String(String::from_utf8_lossy(data).to_string())
+        } else {
+            Value::String(format!("0x{}", hex::encode(data)))
+        });
+    }
+    abi::decode_word(ty, word)
+}
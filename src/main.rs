@@ -2,64 +2,351 @@
 mod compiler;
 mod watcher;
 mod anvil;
+mod ws;
+mod toolchain;
+mod error;
+mod project;
+mod abi;
+mod rpc;
+mod cache;
+mod outline;
+mod debug;
+mod locals;
+mod overrides;
+mod storage;
+mod auth;
+mod config;
+mod metrics;
+mod diff;
+mod console;
+mod simulate;
+mod extra_args;
+mod interact;
+mod history;
+mod inline;
+mod calltree;
+mod analyze;
 
 use axum::{
     extract::{ws::{Message, WebSocket, WebSocketUpgrade}, Path, Query, State},
-    http::{header, HeaderMap, HeaderValue, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{get, post},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post},
     Router,
     Json,
 };
 use clap::Parser;
 use include_dir::{include_dir, Dir};
-use std::{net::SocketAddr, path::PathBuf, process::Command, sync::{Arc, Mutex}};
+use futures::{SinkExt, StreamExt};
+use std::{collections::{BTreeMap, HashMap, HashSet}, net::{IpAddr, SocketAddr}, path::PathBuf, process::Command, sync::{Arc, Mutex, OnceLock}, time::{Duration, Instant}};
 use tokio::sync::broadcast;
-use tower_http::cors::CorsLayer;
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    trace::{DefaultOnResponse, TraceLayer},
+    LatencyUnit,
+};
+use tracing::{Instrument, Level};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use crate::compiler::Compiler;
+use crate::compiler::CompileState;
+use crate::error::{ApiError, ApiResult};
 use walkdir::WalkDir;
 use serde::{Deserialize, Serialize};
-use ethers::types::U256;
+use ethers::types::{Address, U256};
+
+const WS_PING_INTERVAL: Duration = Duration::from_secs(15);
+const WS_PONG_TIMEOUT: Duration = Duration::from_secs(45);
 
 struct AppState {
-    tx: broadcast::Sender<String>,
-    last_msg: Arc<Mutex<Option<String>>>,
+    tx: broadcast::Sender<ws::Broadcast>,
+    compile: Arc<CompileState>,
+    primary_node: Arc<Mutex<anvil::AnvilNode>>,
     fork_node: Arc<Mutex<anvil::AnvilNode>>,
     root_dir: PathBuf,
+    tools: Vec<toolchain::ToolInfo>,
+    allow_non_sol_writes: bool,
+    /// Default for requests that don't name an `rpcUrl`.
+    rpc_url: String,
+    http: reqwest::Client,
+    debug_sessions: Mutex<debug::Sessions>,
+    /// Open WebSocket connections.
+    connections: Mutex<ws::Connections<Message>>,
+    metrics: Arc<metrics::Metrics>,
+    /// Permits for running forge/cast/curl, `--max-concurrent-commands` in all.
+    commands: Arc<tokio::sync::Semaphore>,
+    trace_history: history::TraceHistory,
+    /// Messages each WebSocket client may have waiting to be sent.
+    ws_queue_size: usize,
+    /// `--ui-dir`, served in place of the embedded UI.
+    ui_dir: Option<PathBuf>,
 }
 
 #[derive(Deserialize)]
 struct TraceParams {
+    #[serde(alias = "rpcUrl")]
     rpc_url: Option<String>,
+    /// A built-in node to trace on instead of `rpc_url`.
+    target: Option<NodeTarget>,
+    /// Space-separated flags for `cast run`; see `extra_args::ALLOWED`.
+    extra_args: Option<String>,
+}
+
+/// One of chasm's own anvil nodes, named in place of an RPC URL.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum NodeTarget {
+    /// The primary node.
+    Local,
+    /// The forked node started with `/fork/start`.
+    Fork,
+}
+
+#[derive(Deserialize)]
+struct DebugResolveRequest {
+    contract: String,
+    pc: Option<usize>,
+    pcs: Option<Vec<usize>>,
+}
+
+#[derive(Deserialize)]
+struct DebugStepsRequest {
+    txHash: String,
+    rpcUrl: Option<String>,
+    #[serde(default)]
+    capture: debug::StepOptions,
+}
+
+#[derive(Deserialize)]
+struct DebugSessionRequest {
+    trace: Option<debug::StepTrace>,
+    txHash: Option<String>,
+    rpcUrl: Option<String>,
+    #[serde(default)]
+    capture: debug::StepOptions,
+    /// Contract whose source map resolves top-level steps to lines.
+    contract: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DebugStepRequest {
+    /// `"opcode"` (default) or `"line"`.
+    #[serde(default)]
+    by: String,
+}
+
+#[derive(Deserialize)]
+struct DebugBreakpointsRequest {
+    breakpoints: Vec<debug::Breakpoint>,
+}
+
+#[derive(Deserialize)]
+struct StorageParams {
+    /// Comma-separated slots, decimal or 0x hex
+    slots: String,
+    rpcUrl: Option<String>,
+    contract: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ContractCallRequest {
+    address: String,
+    /// A name, or the full signature for overloaded functions.
+    function: String,
+    #[serde(default)]
+    args: Vec<serde_json::Value>,
+    rpcUrl: Option<String>,
+    /// Signs the transaction for non-view functions.
+    privateKey: Option<String>,
+    /// Wei to send along, decimal or 0x hex.
+    value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FundRequest {
+    address: String,
+    /// A number or numeric string in `unit`, e.g. `100` or `"0.5"`.
+    amount: serde_json::Value,
+    /// `ether` (default), `gwei`, `wei` or any other unit ethers knows.
+    unit: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StorageCompatRequest {
+    /// The deployed implementation.
+    old: String,
+    /// The implementation to upgrade to.
+    new: String,
+}
+
+#[derive(Deserialize)]
+struct CompileFileRequest {
+    path: Option<String>,
+    contract: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FileReadParams {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct FileWriteRequest {
+    path: String,
+    contents: String,
+}
+
+#[derive(Deserialize)]
+struct FormatRequest {
+    /// A `.sol` file or a directory, relative to the root.
+    path: Option<String>,
+    /// Source to format instead of a file.
+    source: Option<String>,
+    /// Only report what would change.
+    #[serde(default)]
+    check: bool,
+}
+
+#[derive(Deserialize)]
+struct AnalyzeRequest {
+    /// Leave out findings of lower impact.
+    minSeverity: Option<analyze::Severity>,
+}
+
+#[derive(Deserialize)]
+struct DecodeSelectorRequest {
+    selector: Option<String>,
+    calldata: Option<String>,
+    signature: Option<String>,
+    /// Also query the openchain signature database; off for offline use
+    #[serde(default)]
+    external: bool,
+}
+
+#[derive(Deserialize)]
+struct RpcUrlParams {
+    rpcUrl: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DecodeLogsRequest {
+    logs: Vec<abi::LogInput>,
+    contract: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SelectorCandidate {
+    signature: String,
+    source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contract: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct TraceCalltreeRequest {
-    rpcUrl: String,
+    rpcUrl: Option<String>,
+    target: Option<NodeTarget>,
     call: serde_json::Value,
     blockTag: Option<String>,
+    #[serde(default)]
+    stateOverrides: overrides::StateOverrides,
+    /// More `cast call` flags; see `extra_args::ALLOWED`.
+    #[serde(default)]
+    extraArgs: Vec<String>,
+    /// For a call to a CREATE2 factory, what to compute `createdAddress` from.
+    create2: Option<Create2Request>,
+}
+
+#[derive(Deserialize)]
+struct Create2AddressRequest {
+    deployer: String,
+    salt: String,
+    initCodeHash: Option<String>,
+    initCode: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AddressRequest {
+    address: String,
+    /// Also report whether there's code at the address on this node.
+    rpcUrl: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeccakRequest {
+    input: String,
+    /// `utf8` (the default) or `hex`.
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EventTopicRequest {
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct UnitsRequest {
+    /// A decimal string (or number) in `from` units.
+    value: serde_json::Value,
+    from: String,
+    to: String,
+}
+
+#[derive(Deserialize)]
+struct Create2Request {
+    salt: String,
+    /// Defaults to `data` after the salt, which is how the deterministic
+    /// deployment proxy takes it.
+    initCode: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct TraceCallRequest {
-    rpcUrl: String,
+    rpcUrl: Option<String>,
+    target: Option<NodeTarget>,
     call: serde_json::Value,
     blockTag: Option<String>,
+    #[serde(default)]
+    stateOverrides: overrides::StateOverrides,
+}
+
+#[derive(Deserialize)]
+struct SimulateRequest {
+    from: Option<String>,
+    to: String,
+    #[serde(default)]
+    data: Option<String>,
+    value: Option<serde_json::Value>,
+    rpcUrl: Option<String>,
+    blockTag: Option<String>,
+    /// Compiled contract name per address, for decoding storage of accounts
+    /// whose code doesn't match a contract exactly.
+    #[serde(default)]
+    contracts: HashMap<String, String>,
 }
 
 #[derive(Deserialize)]
 struct ForkStartRequest {
     rpcUrl: String,
-    blockNumber: Option<u64>,
+    blockNumber: Option<rpc::BlockRef>,
+}
+
+#[derive(Deserialize)]
+struct ForkResetRequest {
+    blockNumber: Option<rpc::BlockRef>,
 }
 
 #[derive(Serialize)]
 struct ForkStatusResponse {
     running: bool,
     rpcUrl: Option<String>,
-    blockNumber: Option<u64>,
+    /// The block the fork was made at, when one was pinned.
+    forkBlock: Option<u64>,
+    /// The fork's own head, which moves on as transactions are mined.
+    currentBlock: Option<u64>,
     port: u16,
+    exitStatus: Option<String>,
+    /// What the node was (or will be) started with.
+    logging: anvil::AnvilLogging,
 }
 
 #[derive(Serialize)]
@@ -95,316 +382,1955 @@ struct ProxyRequest {
 }
 
 async fn handle_proxy_request(
+    State(state): State<Arc<AppState>>,
     Json(payload): Json<ProxyRequest>,
-) -> Response {
-    let client = reqwest::Client::new();
-    let body = serde_json::json!({
-        "jsonrpc": payload.jsonrpc.unwrap_or("2.0".to_string()),
-        "method": payload.method,
-        "params": payload.params.unwrap_or(serde_json::json!([])),
-        "id": payload.id.unwrap_or(1)
-    });
-
-    match client.post(&payload.url).json(&body).send().await {
-        Ok(res) => {
-            let status = StatusCode::from_u16(res.status().as_u16()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-            match res.json::<serde_json::Value>().await {
-                Ok(data) => Json::<serde_json::Value>(data).into_response(),
-                Err(_) => status.into_response()
-            }
-        },
-        Err(e) => Json(serde_json::json!({"error": format!("Proxy failed: {}", e)})).into_response()
+) -> ApiResult<serde_json::Value> {
+    rpc::transport(&payload.url)?;
+    let params = payload.params.unwrap_or(serde_json::json!([]));
+    let mut response = rpc::call_response(&state.http, &payload.url, &payload.method, params, payload.id.unwrap_or(1)).await
+        .map_err(|e| ApiError::Upstream(format!("Proxy failed: {}", e)))?;
+    if let Some(version) = payload.jsonrpc {
+        response["jsonrpc"] = version.into();
     }
+    Ok(Json(response))
 }
 
 static UI_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/ui/dist");
 
+/// Served at `/` when the binary was built before the frontend, so
+/// `UI_DIR` is empty.
+const UI_NOT_BUILT: &str = r#"<!doctype html>
+<html lang="en">
+<head><meta charset="utf-8"><title>ChainSmith</title></head>
+<body style="font-family: sans-serif; max-width: 40em; margin: 4em auto; line-height: 1.5">
+<h1>The UI wasn't built</h1>
+<p>This binary was compiled while <code>ui/dist</code> was empty, so there is no frontend to serve.
+The API is running as usual.</p>
+<p>To include the UI, build the frontend and then rebuild the server:</p>
+<pre>cd ui &amp;&amp; npm install &amp;&amp; npm run build
+cd .. &amp;&amp; cargo build</pre>
+</body>
+</html>
+"#;
+
 #[derive(Parser, Debug)]
 #[command(name = "chasm", about = "ChainSmith CLI")]
 struct Cli {
     #[arg(value_name = "path", default_value = ".")]
     path: PathBuf,
+    /// Allow /files/write to write files other than .sol sources
+    #[arg(long)]
+    allow_non_sol_writes: bool,
+    /// Only compile sources matching this glob (relative to the project
+    /// root) and their imports. Can be given multiple times.
+    #[arg(long = "source", value_name = "GLOB", value_parser = parse_glob)]
+    sources: Vec<glob::Pattern>,
+    /// Leave out sources matching this glob unless something else imports
+    /// them. Can be given multiple times.
+    #[arg(long = "ignore", value_name = "GLOB", value_parser = parse_glob)]
+    ignore: Vec<glob::Pattern>,
+    /// Also compile and watch the sources in this directory, e.g. another
+    /// package of a monorepo. Can be given multiple times.
+    #[arg(long = "watch", value_name = "PATH")]
+    watch: Vec<PathBuf>,
+    /// Only send contracts from source files matching this glob in
+    /// compile_success (all are still compiled). Can be given multiple times.
+    #[arg(long = "contracts-include", value_name = "GLOB", value_parser = parse_glob)]
+    contracts_include: Vec<glob::Pattern>,
+    /// Leave contracts from source files matching this glob out of
+    /// compile_success, e.g. 'lib/**'. Can be given multiple times.
+    #[arg(long = "contracts-exclude", value_name = "GLOB", value_parser = parse_glob)]
+    contracts_exclude: Vec<glob::Pattern>,
+    /// Deployed code size in bytes above which compile_success warns about a
+    /// contract [default: 24576, the EIP-170 limit]
+    #[arg(long, value_name = "BYTES")]
+    size_limit: Option<usize>,
+    /// How many forge/cast/curl commands may run at once; requests beyond
+    /// that get a 429 [default: 8]
+    #[arg(long, value_name = "N")]
+    max_concurrent_commands: Option<usize>,
+    /// How many messages may wait to be sent to one WebSocket client before
+    /// trace lines are dropped [default: 128]
+    #[arg(long, value_name = "N")]
+    ws_queue_size: Option<usize>,
+    /// Seconds to wait for anvil to exit after SIGTERM before killing it
+    /// [default: 3]
+    #[arg(long, value_name = "SECS")]
+    anvil_stop_grace: Option<f64>,
+    /// Pass `--silent` to both anvil nodes, so they print nothing
+    #[arg(long, conflicts_with = "anvil_verbosity")]
+    anvil_silent: bool,
+    /// How many `-v`s to pass to both anvil nodes, up to 5; from 3 they
+    /// print transaction traces [default: 0]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=anvil::MAX_VERBOSITY as i64))]
+    anvil_verbosity: Option<u8>,
+    /// Address to serve the UI and API on [default: 127.0.0.1]
+    #[arg(long, value_name = "ADDR")]
+    host: Option<IpAddr>,
+    /// Port to serve the UI and API on [default: 3000]
+    #[arg(long, value_name = "PORT")]
+    port: Option<u16>,
+    /// Port of the primary anvil node [default: 8545]
+    #[arg(long, value_name = "PORT")]
+    anvil_port: Option<u16>,
+    /// Port of the forked anvil node [default: 8546]
+    #[arg(long, value_name = "PORT")]
+    fork_port: Option<u16>,
+    /// Node used when a request doesn't name one [default: the primary anvil]
+    #[arg(long, value_name = "URL")]
+    rpc_url: Option<String>,
+    /// Compile every source with this solc version instead of picking one
+    /// per pragma
+    #[arg(long, value_name = "VERSION", value_parser = parse_solc_version)]
+    solc: Option<semver::Version>,
+    /// Compile through the Yul IR pipeline, like foundry.toml's `via_ir`
+    /// (which is honored without this). Much slower
+    #[arg(long)]
+    via_ir: bool,
+    /// Print the resolved configuration as JSON and exit
+    #[arg(long)]
+    print_config: bool,
+    /// Allow cross-origin requests from this origin (`*` for any). Can be
+    /// given multiple times. Defaults to any origin on a loopback host and
+    /// none otherwise.
+    #[arg(long = "cors-origin", value_name = "ORIGIN", value_parser = parse_origin)]
+    cors_origins: Vec<HeaderValue>,
+    /// Require `Authorization: Bearer <TOKEN>` on every API route (the UI
+    /// and /healthz stay open; /ws also accepts `?token=`)
+    #[arg(long, value_name = "TOKEN", env = "CHASM_AUTH_TOKEN")]
+    auth_token: Option<String>,
+    /// How log lines are written; RUST_LOG still picks which [default: text]
+    #[arg(long, value_name = "FORMAT", value_enum)]
+    log_format: Option<LogFormat>,
+    /// Only log errors. Ignored when RUST_LOG is set
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log more: -v for debug output from dependencies too, -vv for trace.
+    /// Ignored when RUST_LOG is set
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Don't load the project's `.env` into the environment at startup
+    #[arg(long)]
+    no_dotenv: bool,
+    /// Serve the UI from this directory instead of the one built into the
+    /// binary, e.g. `ui/dist` while `vite build --watch` rebuilds it. Files
+    /// are read on every request, so a reload shows the latest build
+    #[arg(long, value_name = "PATH")]
+    ui_dir: Option<PathBuf>,
+}
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum LogFormat {
+    /// One human-readable line per event
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+    /// Multi-line, with source locations, for development
+    Pretty,
+}
+
+/// Everything `main` runs with: command-line flags, then `chasm.toml`, then
+/// the built-in defaults.
+struct Settings {
+    host: IpAddr,
+    port: u16,
+    anvil_port: u16,
+    fork_port: u16,
+    anvil_stop_grace: Duration,
+    anvil_logging: anvil::AnvilLogging,
+    solc: Option<semver::Version>,
+    /// Only when set by the flag or chasm.toml; otherwise foundry.toml decides.
+    via_ir: Option<bool>,
+    /// Only when configured; defaults to the primary anvil's URL once it's
+    /// up, on whichever port it ended up on.
+    rpc_url: Option<String>,
+    source_filter: compiler::SourceFilter,
+    contract_filter: compiler::ContractFilter,
+    size_limit: usize,
+    max_concurrent_commands: usize,
+    ws_queue_size: usize,
+    cors_origins: Vec<HeaderValue>,
+    allow_non_sol_writes: bool,
+    auth_token: Option<String>,
+    log_format: LogFormat,
+    dotenv: bool,
+    ui_dir: Option<PathBuf>,
+}
+
+fn resolve_settings(root: &std::path::Path, args: Cli, file: config::FileConfig) -> Result<Settings, String> {
+    fn globs(cli: Vec<glob::Pattern>, file: Option<Vec<String>>) -> Result<Vec<glob::Pattern>, String> {
+        if !cli.is_empty() {
+            return Ok(cli);
+        }
+        file.unwrap_or_default().iter().map(|g| parse_glob(g)).collect()
+    }
+
+    let anvil_port = args.anvil_port.or(file.anvil_port).unwrap_or(8545);
+    let rpc_url = args.rpc_url.or(file.rpc_url);
+    if let Some(url) = &rpc_url {
+        rpc::transport(url).map_err(|e| e.to_string())?;
+    }
+    let grace = args.anvil_stop_grace.or(file.anvil_stop_grace)
+        .map(|secs| Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid anvil_stop_grace {}", secs)))
+        .transpose()?;
+    // Flag paths are relative to the working directory, config paths to the root
+    let watch = if args.watch.is_empty() {
+        file.watch.unwrap_or_default().into_iter().map(|p| root.join(p)).collect()
+    } else {
+        args.watch
+    };
+    let extra_dirs = watch.iter()
+        .map(|dir| dunce::canonicalize(dir).map_err(|e| format!("invalid watch path {}: {}", dir.display(), e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let anvil_verbosity = args.anvil_verbosity.or(file.anvil_verbosity).unwrap_or(0);
+    if anvil_verbosity > anvil::MAX_VERBOSITY {
+        return Err(format!("anvil_verbosity must be at most {}", anvil::MAX_VERBOSITY));
+    }
+    let max_concurrent_commands = args.max_concurrent_commands.or(file.max_concurrent_commands).unwrap_or(8);
+    if max_concurrent_commands == 0 {
+        return Err("max_concurrent_commands must be at least 1".to_string());
+    }
+    let ws_queue_size = args.ws_queue_size.or(file.ws_queue_size).unwrap_or(128);
+    if ws_queue_size == 0 {
+        return Err("ws_queue_size must be at least 1".to_string());
+    }
+    let solc = match args.solc {
+        Some(version) => Some(version),
+        None => file.solc.as_deref().map(parse_solc_version).transpose()?,
+    };
+    let ui_dir = args.ui_dir.or_else(|| file.ui_dir.map(|p| root.join(p)))
+        .map(|dir| match dunce::canonicalize(&dir) {
+            Ok(dir) if dir.is_dir() => Ok(dir),
+            Ok(_) => Err(format!("invalid ui_dir {}: not a directory", dir.display())),
+            Err(e) => Err(format!("invalid ui_dir {}: {}", dir.display(), e)),
+        })
+        .transpose()?;
+    let cors_origins = if args.cors_origins.is_empty() {
+        file.cors_origins.unwrap_or_default().iter().map(|o| parse_origin(o)).collect::<Result<_, _>>()?
+    } else {
+        args.cors_origins
+    };
+
+    Ok(Settings {
+        host: args.host.or(file.host).unwrap_or(IpAddr::from([127, 0, 0, 1])),
+        port: args.port.or(file.port).unwrap_or(3000),
+        anvil_port,
+        fork_port: args.fork_port.or(file.fork_port).unwrap_or(8546),
+        anvil_stop_grace: grace.unwrap_or(anvil::DEFAULT_STOP_GRACE),
+        anvil_logging: anvil::AnvilLogging {
+            silent: args.anvil_silent || file.anvil_silent.unwrap_or(false),
+            verbosity: anvil_verbosity,
+        },
+        solc,
+        via_ir: args.via_ir.then_some(true).or(file.via_ir),
+        rpc_url,
+        source_filter: compiler::SourceFilter {
+            include: globs(args.sources, file.sources)?,
+            exclude: globs(args.ignore, file.ignore)?,
+            extra_dirs,
+        },
+        contract_filter: compiler::ContractFilter {
+            include: globs(args.contracts_include, file.contracts_include)?,
+            exclude: globs(args.contracts_exclude, file.contracts_exclude)?,
+        },
+        size_limit: args.size_limit.or(file.size_limit).unwrap_or(compiler::EIP170_SIZE_LIMIT),
+        max_concurrent_commands,
+        ws_queue_size,
+        cors_origins,
+        allow_non_sol_writes: args.allow_non_sol_writes || file.allow_non_sol_writes.unwrap_or(false),
+        auth_token: args.auth_token,
+        log_format: args.log_format.or(file.log_format).unwrap_or_default(),
+        dotenv: !args.no_dotenv && file.dotenv.unwrap_or(true),
+        ui_dir,
+    })
+}
+
+/// Everything startup would resolve for `root`, for `--print-config`.
+fn describe_config(root: &std::path::Path, settings: &Settings) -> serde_json::Value {
+    let info = project::ProjectInfo::detect(root);
+    let via_ir = settings.via_ir.or_else(|| project::foundry_via_ir(root)).unwrap_or(false);
+    let remappings = compiler::Compiler::new(root.to_path_buf(), None, &settings.source_filter.extra_dirs, via_ir)
+        .map(|c| c.remappings())
+        .unwrap_or_default();
+    let globs = |patterns: &[glob::Pattern]| patterns.iter().map(|p| p.as_str().to_string()).collect::<Vec<_>>();
+    serde_json::json!({
+        "root": info.root,
+        "src": info.src,
+        "configFiles": config::existing_files(root),
+        "hasFoundryToml": info.has_foundry_toml,
+        "hasRemappings": info.has_remappings,
+        "hardhat": info.hardhat,
+        "libs": info.libs,
+        "remappings": remappings,
+        "solcVersion": settings.solc.as_ref().map(ToString::to_string).or(info.solc_version),
+        "solcPinned": settings.solc.is_some(),
+        "viaIr": via_ir,
+        "sources": globs(&settings.source_filter.include),
+        "ignore": globs(&settings.source_filter.exclude),
+        "watch": settings.source_filter.extra_dirs,
+        "contractsInclude": globs(&settings.contract_filter.include),
+        "contractsExclude": globs(&settings.contract_filter.exclude),
+        "sizeLimit": settings.size_limit,
+        "maxConcurrentCommands": settings.max_concurrent_commands,
+        "wsQueueSize": settings.ws_queue_size,
+        "ignoredDirs": project::IGNORED_DIRS,
+        "host": settings.host,
+        "port": settings.port,
+        "anvilPort": settings.anvil_port,
+        "forkPort": settings.fork_port,
+        "anvilStopGrace": settings.anvil_stop_grace.as_secs_f64(),
+        "anvilSilent": settings.anvil_logging.silent,
+        "anvilVerbosity": settings.anvil_logging.verbosity,
+        "rpcUrl": settings.rpc_url.clone().unwrap_or_else(|| format!("http://127.0.0.1:{}", settings.anvil_port)),
+        "corsOrigins": settings.cors_origins.iter().filter_map(|o| o.to_str().ok()).collect::<Vec<_>>(),
+        "allowNonSolWrites": settings.allow_non_sol_writes,
+        "authToken": settings.auth_token.is_some(),
+        "logFormat": settings.log_format,
+        "dotenv": settings.dotenv,
+        "uiDir": settings.ui_dir,
+    })
+}
+
+fn parse_solc_version(s: &str) -> Result<semver::Version, String> {
+    semver::Version::parse(s.trim_start_matches('v')).map_err(|e| format!("invalid solc version {:?}: {}", s, e))
+}
+
+fn parse_origin(s: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(s).map_err(|e| format!("invalid origin {:?}: {}", s, e))
+}
+
+/// Builds the CORS policy for the router from `--cors-origin`.
+fn cors_layer(origins: Vec<HeaderValue>, host: IpAddr) -> CorsLayer {
+    let any = origins.iter().any(|o| o == "*");
+    if any || (origins.is_empty() && host.is_loopback()) {
+        if !host.is_loopback() {
+            tracing::warn!("Allowing cross-origin requests from any origin while listening on {}", host);
+        }
+        return CorsLayer::permissive();
+    }
+    if origins.is_empty() {
+        tracing::info!("Not on a loopback address, so cross-origin requests are refused; see --cors-origin");
+    }
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+}
+
+/// The log filter when RUST_LOG isn't set, from `--quiet`/`--verbose`.
+fn log_filter(quiet: bool, verbose: u8) -> &'static str {
+    match (quiet, verbose) {
+        (true, _) => "error",
+        (false, 0) => "chasm=debug,tower_http=debug",
+        (false, 1) => "debug",
+        (false, _) => "trace",
+    }
+}
+
+fn parse_glob(s: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(s).map_err(|e| format!("invalid glob {:?}: {}", s, e))
 }
 
 #[tokio::main]
 async fn main() {
     let args = Cli::parse();
-    let root_dir = args.path.canonicalize().unwrap_or(args.path);
+    // dunce keeps plain drive paths on Windows (`C:\x` rather than the
+    // verbatim `\\?\C:\x`), so they compare equal to the paths notify and
+    // foundry-compilers report; long paths and UNC shares it can't shorten
+    // keep their verbatim form, which the watcher accepts as is
+    let root_dir = dunce::canonicalize(&args.path).unwrap_or(args.path.clone());
+    let print_config = args.print_config;
+    let log_filter = log_filter(args.quiet, args.verbose);
+    let settings = match config::load(&root_dir).and_then(|file| resolve_settings(&root_dir, args, file)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if print_config {
+        let config = describe_config(&root_dir, &settings);
+        println!("{}", serde_json::to_string_pretty(&config).unwrap_or_default());
+        return;
+    }
+
     // Initialize logging
-    tracing_subscriber::registry()
+    let registry = tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "chainsmith=debug,tower_http=debug".into()),
-        ))
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+            std::env::var("RUST_LOG").unwrap_or_else(|_| log_filter.into()),
+        ));
+    match settings.log_format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).init(),
+        LogFormat::Json => registry.with(tracing_subscriber::fmt::layer().json()).init(),
+        LogFormat::Pretty => registry.with(tracing_subscriber::fmt::layer().pretty()).init(),
+    }
 
     tracing::info!("Starting ChainSmith...");
 
+    if settings.dotenv {
+        match project::load_dotenv(&root_dir) {
+            Ok(keys) if !keys.is_empty() => tracing::info!("Loaded {} from .env", keys.join(", ")),
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to load .env: {}", e),
+        }
+    }
+
+    if let Some(dir) = &settings.ui_dir {
+        tracing::info!("Serving the UI from {}", dir.display());
+    } else if UI_DIR.get_file("index.html").is_none() {
+        tracing::warn!("No UI embedded: ui/dist had no index.html at build time. Run `npm run build` in ui/ and rebuild to include it");
+    }
+
+    let tools = toolchain::detect_and_report();
+
     // Channel for broadcasting updates to frontend
     let (tx, _rx) = broadcast::channel(100);
-    let last_msg = Arc::new(Mutex::new(None));
+    let metrics = Arc::new(metrics::Metrics::default());
+    let compile = Arc::new(CompileState::new(tx.clone(), settings.source_filter, settings.contract_filter, settings.size_limit, settings.solc, settings.via_ir, metrics.clone()));
 
     // Start Anvil (Primary)
-    let mut anvil = anvil::AnvilNode::new(8545);
+    let mut anvil = anvil::AnvilNode::new(settings.anvil_port, settings.anvil_stop_grace, settings.anvil_logging);
     if let Err(e) = anvil.start() {
         tracing::error!("Failed to start anvil: {}", e);
     } else {
-        tracing::info!("Anvil started on port 8545");
+        tracing::info!("Anvil started on port {}", anvil.port());
     }
+    let rpc_url = settings.rpc_url.unwrap_or_else(|| anvil.url());
+    let primary_node = Arc::new(Mutex::new(anvil));
 
     // Forked Anvil (Optional)
-    let fork_node = Arc::new(Mutex::new(anvil::AnvilNode::new(8546)));
+    let fork_node = Arc::new(Mutex::new(anvil::AnvilNode::new(settings.fork_port, settings.anvil_stop_grace, settings.anvil_logging)));
+
+    // Watch for anvil children dying underneath us
+    anvil::spawn_reaper(primary_node.clone(), fork_node.clone(), tx.clone());
     
+    if compile.via_ir(&root_dir) {
+        tracing::warn!("Compiling via IR; expect compiles to take much longer");
+    }
+
     // Initial Compile
     tracing::info!("Performing initial compilation...");
-    let compiler = Compiler::new(root_dir.clone()).unwrap();
-    match compiler.compile_to_json() {
-        Ok(json) => {
-            tracing::info!("Initial compilation successful. Payload size: {}", json.len());
-            if let Ok(mut lock) = last_msg.lock() {
-                *lock = Some(json);
+    if project::is_hardhat(&root_dir) {
+        tracing::info!("Detected Hardhat project, resolving imports from node_modules");
+    }
+    if compile.load_cached(&root_dir) {
+        // Still refresh, since dependencies aren't part of the cache key
+        tracing::info!("Loaded cached artifacts, recompiling in the background");
+        let compile = compile.clone();
+        let root = root_dir.clone();
+        tokio::task::spawn_blocking(move || compile.compile_and_publish(root, &[]));
+    } else {
+        compile.compile_and_publish(root_dir.clone(), &[]);
+    }
+
+    // Start File Watcher
+    if let Err(e) = watcher::setup_watcher(root_dir.clone(), compile.clone()).await {
+        tracing::error!("Failed to setup watcher: {}", e);
+    }
+
+    let trace_history = history::TraceHistory::load(&root_dir);
+    let app_state = Arc::new(AppState {
+        tx,
+        compile,
+        primary_node,
+        fork_node,
+        root_dir,
+        tools,
+        allow_non_sol_writes: settings.allow_non_sol_writes,
+        rpc_url,
+        http: reqwest::Client::new(),
+        debug_sessions: Mutex::new(debug::Sessions::default()),
+        connections: Mutex::new(ws::Connections::default()),
+        metrics,
+        commands: Arc::new(tokio::sync::Semaphore::new(settings.max_concurrent_commands)),
+        trace_history,
+        ws_queue_size: settings.ws_queue_size,
+        ui_dir: settings.ui_dir,
+    });
+
+    // Build our application with a route
+    let auth_token: auth::AuthToken = settings.auth_token.map(Arc::from);
+    let app = Router::new()
+        .route("/version", get(version))
+        .route("/metrics", get(metrics_text))
+        .route("/toolchain", get(toolchain_info))
+        .route("/project/info", get(project_info))
+        .route("/files", get(list_files))
+        .route("/files/read", get(read_file))
+        .route("/files/write", post(write_file))
+        .route("/compile/file", post(compile_file))
+        .route("/compile/check", get(compile_check))
+        .route("/compile/diff", get(compile_diff))
+        .route("/compile/inline", post(compile_inline))
+        .route("/ws", get(ws_handler))
+        .route("/connections", get(list_connections))
+        .route("/inspect/:contract", get(inspect_storage))
+        .route("/storage/:address", get(read_storage))
+        .route("/storage/:contract/:address", get(read_contract_storage))
+        .route("/storage/compat", post(storage_compat))
+        .route("/flatten/:contract", get(flatten_contract))
+        .route("/format", post(format_source))
+        .route("/analyze", post(analyze_project))
+        .route("/contract/:name/selectors", get(contract_selectors))
+        .route("/contract/:name/outline", get(contract_outline))
+        .route("/contract/:name/methods", get(contract_methods))
+        .route("/contract/:name/solc-input", get(contract_solc_input))
+        .route("/contract/:name/call", post(contract_call))
+        .route("/create2", post(create2))
+        .route("/abi/signatures", post(abi_signatures))
+        .route("/util/address", post(check_address))
+        .route("/util/keccak", post(keccak))
+        .route("/util/event-topic", post(event_topic))
+        .route("/util/units", post(convert_units))
+        .route("/decode/selector", post(decode_selector))
+        .route("/decode/logs", post(decode_logs))
+        .route("/tx/:hash/receipt", get(get_receipt))
+        .route("/anvil/txpool", get(anvil_txpool))
+        .route("/anvil/accounts", get(anvil_accounts))
+        .route("/anvil/fund", post(anvil_fund))
+        .route("/trace/:tx_hash", get(get_trace))
+        .route("/trace/block/:number/:index", get(trace_by_position))
+        .route("/trace/history", get(list_trace_history).delete(clear_trace_history))
+        .route("/trace/history/:id/replay", post(replay_trace))
+        .route("/trace/:tx_hash/stream", post(stream_trace))
+        .route("/trace/calltree", post(get_trace_calltree))
+        .route("/trace/calltree/decoded", post(decoded_trace_calltree))
+        .route("/trace/call", post(get_trace_call))
+        .route("/simulate", post(simulate_call))
+        .route("/debug/resolve", post(debug_resolve))
+        .route("/debug/steps", post(debug_steps))
+        .route("/debug/session", post(create_debug_session))
+        .route("/debug/session/:id", delete(delete_debug_session))
+        .route("/debug/session/:id/step", post(step_debug_session))
+        .route("/debug/session/:id/continue", post(continue_debug_session))
+        .route("/debug/session/:id/breakpoints", post(set_debug_breakpoints))
+        .route("/debug/session/:id/locals", get(debug_session_locals))
+        .route("/fork/start", post(start_fork))
+        .route("/fork/stop", post(stop_fork))
+        .route("/fork/reset", post(reset_fork))
+        .route("/fork/status", get(fork_status))
+        .route("/keystores", get(list_keystores))
+        .route("/keystores/unlock", post(unlock_keystore))
+        .route("/keystores/create", post(create_keystore))
+        .route("/keystores/remove", post(remove_keystore))
+        .route("/proxy", post(handle_proxy_request))
+        // Everything above needs the auth token; health checks and the UI
+        // itself don't
+        .route_layer(axum::middleware::from_fn_with_state(auth_token, auth::require_token))
+        .route("/healthz", get(healthz))
+        .route("/", get(serve_ui_root))
+        .route("/*path", get(serve_ui))
+        // WebSocket frames aren't covered: axum's tungstenite backend has no
+        // permessage-deflate support, so only plain HTTP bodies get compressed.
+        .layer(CompressionLayer::new())
+        .layer(
+            TraceLayer::new_for_http()
+                // Path only: query strings can carry the /ws auth token
+                .make_span_with(|req: &axum::extract::Request| {
+                    tracing::info_span!("request", method = %req.method(), path = %req.uri().path())
+                })
+                .on_response(DefaultOnResponse::new().level(Level::INFO).latency_unit(LatencyUnit::Millis)),
+        )
+        .layer(cors_layer(settings.cors_origins, settings.host))
+        .with_state(app_state);
+
+    let addr = SocketAddr::new(settings.host, settings.port);
+    tracing::info!("ChainSmith UI listening on http://{}", addr);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
+
+async fn serve_ui_root(state: State<Arc<AppState>>, headers: HeaderMap) -> Response {
+    if state.ui_dir.is_none() && UI_DIR.get_file("index.html").is_none() {
+        return (StatusCode::NOT_FOUND, Html(UI_NOT_BUILT)).into_response();
+    }
+    serve_ui(state, Path("".to_string()), headers).await
+}
+
+async fn metrics_text(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"))],
+        state.metrics.render(),
+    ).into_response()
+}
+
+async fn healthz(State(state): State<Arc<AppState>>) -> Response {
+    let anvil_running = state.primary_node.lock().unwrap().is_running();
+    if anvil_running {
+        Json(serde_json::json!({ "status": "ok", "anvil": "running" })).into_response()
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({ "status": "degraded", "anvil": "stopped" }))).into_response()
+    }
+}
+
+async fn version(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let tools: serde_json::Map<String, serde_json::Value> = state.tools.iter()
+        .map(|tool| (tool.name.clone(), serde_json::json!(tool.version)))
+        .collect();
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "tools": tools,
+    }))
+}
+
+async fn toolchain_info(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "tools": state.tools }))
+}
+
+async fn project_info(State(state): State<Arc<AppState>>) -> Json<project::ProjectInfo> {
+    Json(project::ProjectInfo::detect(&state.root_dir))
+}
+
+async fn list_files(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "files": project::file_tree(&state.root_dir) }))
+}
+
+async fn read_file(
+    Query(params): Query<FileReadParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let path = project::resolve_in_root(&state.root_dir, &params.path)
+        .ok_or_else(|| ApiError::BadRequest(format!("Path {} is outside the project root", params.path)))?;
+    if !path.is_file() {
+        return Err(ApiError::NotFound(format!("File {} not found", params.path)));
+    }
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| ApiError::Internal(format!("Failed to read {}: {}", params.path, e)))?;
+    Ok(Json(serde_json::json!({ "path": params.path, "contents": contents })))
+}
+
+async fn write_file(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<FileWriteRequest>,
+) -> ApiResult<serde_json::Value> {
+    let path = project::resolve_in_root(&state.root_dir, &payload.path)
+        .ok_or_else(|| ApiError::BadRequest(format!("Path {} is outside the project root", payload.path)))?;
+    let is_sol = path.extension().is_some_and(|ext| ext == "sol");
+    if !is_sol && !state.allow_non_sol_writes {
+        return Err(ApiError::BadRequest("Only .sol files can be written (start chasm with --allow-non-sol-writes to lift this)".to_string()));
+    }
+    if path.is_dir() {
+        return Err(ApiError::BadRequest(format!("{} is a directory", payload.path)));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| ApiError::Internal(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+    // The watcher picks the change up and recompiles
+    std::fs::write(&path, &payload.contents)
+        .map_err(|e| ApiError::Internal(format!("Failed to write {}: {}", payload.path, e)))?;
+    tracing::info!("Wrote {} bytes to {}", payload.contents.len(), path.display());
+
+    Ok(Json(serde_json::json!({ "status": "success", "path": payload.path, "bytes": payload.contents.len() })))
+}
+
+/// Compiles the project and reports whether it built, with its errors and
+/// warnings but none of the artifacts.
+async fn compile_check(State(state): State<Arc<AppState>>) -> ApiResult<serde_json::Value> {
+    let compile = state.compile.clone();
+    let root = state.root_dir.clone();
+    let diagnostics = tokio::task::spawn_blocking(move || compile.check(root))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Compile task failed: {}", e)))?
+        .map_err(|e| ApiError::Upstream(format!("Compilation failed to run: {}", e)))?;
+
+    let count = |severity: &str| diagnostics.iter().filter(|d| d.severity == severity).count();
+    let error_count = count("error");
+    Ok(Json(serde_json::json!({
+        "success": error_count == 0,
+        "errorCount": error_count,
+        "warningCount": count("warning"),
+        "diagnostics": diagnostics,
+    })))
+}
+
+#[derive(Deserialize)]
+struct InlineCompileRequest {
+    /// File name to Solidity source.
+    sources: BTreeMap<String, String>,
+    /// solc's standard JSON `settings`.
+    settings: Option<foundry_compilers::artifacts::Settings>,
+}
+
+/// Compiles the sources in the request on their own, for scratch
+/// experiments; the project isn't read and nothing is cached or broadcast.
+async fn compile_inline(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<InlineCompileRequest>,
+) -> ApiResult<inline::InlineOutput> {
+    if payload.sources.is_empty() {
+        return Err(ApiError::BadRequest("No sources given".to_string()));
+    }
+    let pinned = state.compile.solc_version().cloned();
+    let output = tokio::task::spawn_blocking(move || inline::compile(payload.sources, payload.settings, pinned.as_ref()))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Compile task failed: {}", e)))?
+        .map_err(|e| ApiError::Upstream(format!("Compilation failed to run: {}", e)))?;
+    Ok(Json(output))
+}
+
+/// What changed between the last two successful compiles.
+async fn compile_diff(State(state): State<Arc<AppState>>) -> ApiResult<serde_json::Value> {
+    let previous = state.compile.previous.read().unwrap();
+    if previous.is_empty() {
+        return Err(ApiError::NotFound("No earlier compile to compare against yet".to_string()));
+    }
+    let current = state.compile.contracts.read().unwrap();
+    let (contracts, summary) = diff::compare(&previous, &current);
+    Ok(Json(serde_json::json!({ "summary": summary, "contracts": contracts })))
+}
+
+/// Compiles a single file (by path or by contract name) and its imports
+/// without touching the cached project-wide compile.
+async fn compile_file(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CompileFileRequest>,
+) -> ApiResult<serde_json::Value> {
+    let path = match (&payload.path, &payload.contract) {
+        (Some(rel), _) => project::resolve_in_root(&state.root_dir, rel)
+            .ok_or_else(|| ApiError::BadRequest(format!("Path {} is outside the project root", rel)))?,
+        (None, Some(name)) => project::find_contract_file(&state.root_dir, name)
+            .ok_or_else(|| ApiError::NotFound(format!("Contract file for {} not found", name)))?,
+        (None, None) => return Err(ApiError::BadRequest("Either path or contract is required".to_string())),
+    };
+    if !path.is_file() {
+        return Err(ApiError::NotFound(format!("File {} not found", path.display())));
+    }
+
+    let compile = state.compile.clone();
+    let root = state.root_dir.clone();
+    let file = path.clone();
+    let contracts = tokio::task::spawn_blocking(move || {
+        compile.compiler(root).and_then(|compiler| compiler.compile_files(vec![file]))
+    })
+        .await
+        .map_err(|e| ApiError::Internal(format!("Compile task failed: {}", e)))?
+        .map_err(|e| ApiError::BadRequest(format!("Compilation failed: {}", e)))?;
+
+    let rel = path.strip_prefix(&state.root_dir).unwrap_or(&path).to_path_buf();
+    Ok(Json(serde_json::json!({ "file": rel, "contracts": contracts })))
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
+) -> Response {
+    ws.on_upgrade(|socket| handle_socket(socket, state))
+}
+
+#[derive(Deserialize)]
+struct ClientCommand {
+    subscribe: Option<Vec<String>>,
+    action: Option<String>,
+}
+
+async fn handle_socket(socket: WebSocket, state: Arc<AppState>) {
+    let _connection = state.metrics.ws_connection();
+    let (mut sink, mut stream) = socket.split();
+    let outbox = Arc::new(ws::Outbox::new(state.ws_queue_size));
+    let registration = ws::Connections::register(&state.connections, outbox.clone());
+    let id = registration.id;
+    tracing::debug!("WebSocket connection {} opened", id);
+    let mut writer = {
+        let outbox = outbox.clone();
+        tokio::spawn(async move {
+            loop {
+                if sink.send(outbox.pop().await).await.is_err() {
+                    break;
+                }
             }
-        },
-        Err(e) => {
-            tracing::error!("Initial compilation failed: {}", e);
-            let err_msg = format!("{{\"type\": \"compile_error\", \"error\": \"{}\"}}", e);
-            if let Ok(mut lock) = last_msg.lock() {
-                *lock = Some(err_msg);
+        })
+    };
+
+    let cached_msg = {
+        let lock = state.compile.last_msg.lock().unwrap();
+        lock.clone()
+    };
+
+    if let Some(msg) = cached_msg {
+        outbox.push(ws::Delivery::Latest, Message::Text(msg));
+    }
+
+    let mut rx = state.tx.subscribe();
+    let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    // None means the client never subscribed and gets every channel
+    let mut subscriptions: Option<HashSet<ws::Channel>> = None;
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        // Skipped messages may include compile results, so
+                        // resync the client with the latest one.
+                        tracing::warn!("WebSocket client lagged behind by {} messages", skipped);
+                        let cached = state.compile.last_msg.lock().unwrap().clone();
+                        match cached {
+                            Some(json) => ws::Broadcast { channel: ws::Channel::Compile, delivery: ws::Delivery::Latest, json },
+                            None => continue,
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if subscriptions.as_ref().is_some_and(|subs| !subs.contains(&msg.channel)) {
+                    continue;
+                }
+                let dropped = outbox.push(msg.delivery, Message::Text(msg.json));
+                if dropped > 0 {
+                    tracing::debug!("WebSocket client is behind, dropped {} queued messages", dropped);
+                }
+            }
+            // The writer stops once the connection can't be written to
+            _ = &mut writer => break,
+            incoming = stream.next() => {
+                match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(Message::Text(text))) => {
+                        last_seen = Instant::now();
+                        let command = match serde_json::from_str::<ClientCommand>(&text) {
+                            Ok(command) => command,
+                            Err(e) => {
+                                tracing::warn!("Ignoring malformed WebSocket message: {}", e);
+                                continue;
+                            }
+                        };
+                        if let Some(channels) = command.subscribe {
+                            let subs: HashSet<ws::Channel> = channels.iter()
+                                .filter_map(|name| {
+                                    let channel = ws::Channel::from_name(name);
+                                    if channel.is_none() {
+                                        tracing::warn!("Ignoring unknown WebSocket channel: {}", name);
+                                    }
+                                    channel
+                                })
+                                .collect();
+                            state.connections.lock().unwrap().set_subscriptions(id, subs.clone());
+                            subscriptions = Some(subs);
+                        }
+                        match command.action.as_deref() {
+                            // The result reaches every client through the usual broadcast
+                            Some("recompile") => {
+                                tracing::info!("Recompile requested over WebSocket");
+                                let compile = state.compile.clone();
+                                let root = state.root_dir.clone();
+                                tokio::task::spawn_blocking(move || compile.compile_and_publish(root, &[]));
+                            }
+                            Some(action) => tracing::warn!("Ignoring unknown WebSocket action: {}", action),
+                            None => {}
+                        }
+                    }
+                    // Any frame (usually the pong to our ping) proves the client is alive
+                    Some(Ok(_)) => last_seen = Instant::now(),
+                }
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > WS_PONG_TIMEOUT {
+                    tracing::info!("WebSocket client stopped responding, dropping connection");
+                    break;
+                }
+                outbox.push(ws::Delivery::Always, Message::Ping(Vec::new()));
+            }
+        }
+    }
+    writer.abort();
+    drop(registration);
+    tracing::debug!("WebSocket connection {} closed", id);
+}
+
+async fn list_connections(State(state): State<Arc<AppState>>) -> ApiResult<serde_json::Value> {
+    Ok(Json(serde_json::json!({ "connections": state.connections.lock().unwrap().list() })))
+}
+
+async fn inspect_storage(
+    Path(contract): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    tracing::info!("Inspecting storage for {}", contract);
+
+    let current_dir = state.root_dir.clone();
+    let src_path = project::source_dir(&current_dir);
+
+    let target = if let Some(path) = project::find_contract_file(&current_dir, &contract) {
+        format!("{}:{}", path.display(), contract)
+    } else {
+        contract
+    };
+
+    let output = run_timed(&state, Command::new("forge")
+        .arg("inspect")
+        .arg(&target)
+        .arg("storage")
+        .arg("--json")
+        .arg("--root")
+        .arg(&current_dir)
+        .arg("--contracts")
+        .arg(&src_path))?;
+
+    if !output.status.success() {
+        return Err(ApiError::Upstream(error::command_failed("Forge failed", &output)));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    serde_json::from_str::<serde_json::Value>(&stdout)
+        .map(Json)
+        .map_err(|_| ApiError::Upstream("Failed to parse forge output".to_string()))
+}
+
+/// Reads raw storage slots of `address`, decoding them against
+/// `contract`'s storage layout when given.
+async fn read_storage(
+    Path(address): Path<String>,
+    Query(params): Query<StorageParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = params.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    let slots = params.slots.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| storage::parse_slot(s).ok_or_else(|| ApiError::BadRequest(format!("Invalid slot {}", s))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if slots.is_empty() {
+        return Err(ApiError::BadRequest("At least one slot is required".to_string()));
+    }
+    let layout = match &params.contract {
+        Some(name) => Some(state.compile.contract(name)
+            .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?
+            .artifact.storage_layout
+            .ok_or_else(|| ApiError::NotFound(format!("No storage layout for {}", name)))?),
+        None => None,
+    };
+
+    let reads = slots.iter().map(|slot| rpc::call(
+        &state.http,
+        &rpc_url,
+        "eth_getStorageAt",
+        serde_json::json!([address, storage::slot_hex(*slot), "latest"]),
+    ));
+    let values = futures::future::join_all(reads).await;
+
+    let mut results = Vec::new();
+    for (slot, value) in slots.iter().zip(values) {
+        let value = value?;
+        let word = storage::parse_word(&value)
+            .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_getStorageAt result {}", value)))?;
+        let mut entry = serde_json::json!({
+            "slot": storage::slot_hex(*slot),
+            "value": format!("0x{}", ethers::utils::hex::encode(word)),
+        });
+        if let Some(layout) = &layout {
+            entry["decoded"] = serde_json::json!(storage::decode_slot(layout, *slot, &word));
+        }
+        results.push(entry);
+    }
+    Ok(Json(serde_json::json!({ "address": address, "slots": results })))
+}
+
+/// Checks that upgrading a proxy from `old` to `new` keeps the storage layout.
+async fn storage_compat(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<StorageCompatRequest>,
+) -> ApiResult<serde_json::Value> {
+    let layout = |name: &str| state.compile.contract(name)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?
+        .artifact.storage_layout
+        .ok_or_else(|| ApiError::NotFound(format!("No storage layout for {}", name)));
+    let compat = storage::compatibility(&layout(&payload.old)?, &layout(&payload.new)?);
+    if !compat.compatible {
+        tracing::info!("{} -> {}: {} storage layout issues", payload.old, payload.new, compat.issues.len());
+    }
+    Ok(Json(serde_json::json!({
+        "old": payload.old,
+        "new": payload.new,
+        "compatible": compat.compatible,
+        "issues": compat.issues,
+        "renamed": compat.renamed,
+        "appended": compat.appended,
+    })))
+}
+
+/// Reads every single-slot variable in `contract`'s storage layout from
+/// `address`. Mappings, dynamic arrays and strings are listed but skipped.
+async fn read_contract_storage(
+    Path((contract, address)): Path<(String, String)>,
+    Query(params): Query<RpcUrlParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = params.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    let layout = state.compile.contract(&contract)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", contract)))?
+        .artifact.storage_layout
+        .ok_or_else(|| ApiError::NotFound(format!("No storage layout for {}", contract)))?;
+
+    // Packed variables share a slot, so read each slot once
+    let mut slots: Vec<U256> = layout.storage.iter()
+        .filter_map(|var| match storage::readable(&layout, var) {
+            storage::Readable::Slot { slot, .. } => Some(slot),
+            storage::Readable::Skipped(_) => None,
+        })
+        .collect();
+    slots.sort();
+    slots.dedup();
+    let reads = slots.iter().map(|slot| rpc::call(
+        &state.http,
+        &rpc_url,
+        "eth_getStorageAt",
+        serde_json::json!([address, storage::slot_hex(*slot), "latest"]),
+    ));
+    let mut words = HashMap::new();
+    for (slot, value) in slots.iter().zip(futures::future::join_all(reads).await) {
+        let value = value?;
+        let word = storage::parse_word(&value)
+            .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_getStorageAt result {}", value)))?;
+        words.insert(*slot, word);
+    }
+
+    let variables: Vec<serde_json::Value> = layout.storage.iter().map(|var| {
+        let ty = storage::type_label(&layout, var);
+        let (value, skipped) = match storage::readable(&layout, var) {
+            storage::Readable::Slot { slot, offset, size } => {
+                let value = words.get(&slot).and_then(|word| storage::decode_packed(word, offset, size, ty));
+                (value, None)
+            }
+            storage::Readable::Skipped(reason) => (None, Some(reason)),
+        };
+        serde_json::json!({
+            "label": var.label,
+            "type": ty,
+            "slot": var.slot,
+            "offset": var.offset,
+            "value": value,
+            "skipped": skipped,
+        })
+    }).collect();
+    Ok(Json(serde_json::json!({ "contract": contract, "address": address, "variables": variables })))
+}
+
+async fn flatten_contract(
+    Path(contract): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<String, ApiError> {
+    let path = project::contract_file_in(state.compile.source_files(&state.root_dir), &contract)
+        .ok_or_else(|| ApiError::NotFound(format!("No source file found for {}", contract)))?;
+    tracing::info!("Flattening {}", path.display());
+
+    // forge handles import cycles and pragma/SPDX de-duplication itself
+    let output = run_timed(&state, Command::new("forge")
+        .arg("flatten")
+        .arg(&path)
+        .arg("--root")
+        .arg(&state.root_dir))?;
+
+    if !output.status.success() {
+        return Err(ApiError::Upstream(error::command_failed("Flatten failed", &output)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Stands in for the file name of in-memory sources in `/format`'s `changed`.
+const FORMAT_SOURCE_NAME: &str = "<source>";
+
+/// Formats a file, directory or in-memory source with `forge fmt`, honoring
+/// the project's `[fmt]` settings. Files are rewritten in place unless
+/// `check` is set; either way `changed` lists the files that (would) change.
+async fn format_source(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<FormatRequest>,
+) -> ApiResult<serde_json::Value> {
+    static SCRATCH_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let (target, scratch) = match (&payload.path, &payload.source) {
+        (Some(rel), None) => {
+            let path = project::resolve_in_root(&state.root_dir, rel)
+                .ok_or_else(|| ApiError::BadRequest(format!("Path {} is outside the project root", rel)))?;
+            if !path.exists() {
+                return Err(ApiError::NotFound(format!("{} not found", rel)));
             }
+            (path, false)
+        }
+        (None, Some(source)) => {
+            let id = SCRATCH_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("chasm-fmt-{}-{}.sol", std::process::id(), id));
+            std::fs::write(&path, source)
+                .map_err(|e| ApiError::Internal(format!("Failed to write {}: {}", path.display(), e)))?;
+            (path, true)
+        }
+        _ => return Err(ApiError::BadRequest("Provide either path or source".to_string())),
+    };
+    let result = format_target(&state, &target, payload.check);
+    let source = (scratch || target.is_file()).then(|| std::fs::read_to_string(&target).ok()).flatten();
+    if scratch {
+        let _ = std::fs::remove_file(&target);
+    }
+    let (changed, diff) = result?;
+    let changed: Vec<String> = changed.iter()
+        .map(|file| if scratch {
+            FORMAT_SOURCE_NAME.to_string()
+        } else {
+            file.strip_prefix(&state.root_dir).unwrap_or(file).to_string_lossy().to_string()
+        })
+        .collect();
+    Ok(Json(serde_json::json!({ "changed": changed, "diff": diff, "source": source })))
+}
+
+/// Runs `forge fmt --check` on `target`, then formats it for real if
+/// anything would change and `check` isn't set. Returns the files forge
+/// reported a diff for, and the diff.
+fn format_target(state: &AppState, target: &std::path::Path, check: bool) -> Result<(Vec<PathBuf>, String), ApiError> {
+    let fmt = |check: bool| {
+        let mut cmd = Command::new("forge");
+        cmd.current_dir(&state.root_dir).arg("fmt").arg("--root").arg(&state.root_dir);
+        if check {
+            cmd.arg("--check");
+        }
+        run_timed(state, cmd.arg(target))
+    };
+
+    // --check exits 1 with a diff per file that isn't formatted
+    let output = fmt(true)?;
+    let diff = String::from_utf8_lossy(&output.stdout).into_owned();
+    let changed: Vec<PathBuf> = diff.lines()
+        .filter_map(|line| line.strip_prefix("Diff in ")?.strip_suffix(':'))
+        .map(|file| state.root_dir.join(file))
+        .collect();
+    if !output.status.success() && changed.is_empty() {
+        return Err(ApiError::Upstream(error::command_failed("Format failed", &output)));
+    }
+    if !check && !changed.is_empty() {
+        let output = fmt(false)?;
+        if !output.status.success() {
+            return Err(ApiError::Upstream(error::command_failed("Format failed", &output)));
+        }
+    }
+    Ok((changed, diff))
+}
+
+/// Runs slither over the project and returns its findings.
+async fn analyze_project(
+    State(state): State<Arc<AppState>>,
+    payload: Option<Json<AnalyzeRequest>>,
+) -> ApiResult<serde_json::Value> {
+    let min_severity = payload.and_then(|Json(p)| p.minSeverity).unwrap_or(analyze::Severity::Optimization);
+    tracing::info!("Analyzing {} with slither", state.root_dir.display());
+    let output = run_timed(&state, Command::new("slither")
+        .current_dir(&state.root_dir)
+        .arg(".")
+        .arg("--json")
+        .arg("-"))?;
+
+    // slither exits non-zero whenever it finds something, so only its
+    // report says whether the run itself worked
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Err(ApiError::Upstream(error::command_failed("Slither failed", &output)));
+    }
+    let findings = analyze::findings(&stdout, min_severity)
+        .map_err(ApiError::Upstream)?;
+    Ok(Json(serde_json::json!({ "findings": findings })))
+}
+
+fn contract_abi(state: &AppState, name: &str) -> Result<ethers::abi::Abi, ApiError> {
+    let contract = state.compile.contract(name)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?;
+    contract.ethers_abi()
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} has no ABI", name)))
+}
+
+/// `/contract/:name/selectors` for an ABI from anywhere, not just the
+/// compile cache.
+async fn abi_signatures(Json(fragments): Json<Vec<serde_json::Value>>) -> ApiResult<serde_json::Value> {
+    let (selectors, errors) = abi::fragment_selectors(&fragments);
+    Ok(Json(serde_json::json!({
+        "functions": selectors.functions,
+        "events": selectors.events,
+        "errors": errors,
+    })))
+}
+
+async fn contract_selectors(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<abi::Selectors> {
+    let abi = contract_abi(&state, &name)?;
+    Ok(Json(abi::selectors(&abi)))
+}
+
+async fn contract_methods(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<abi::Methods> {
+    let contract = state.compile.contract(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?;
+    let methods = contract.artifact.abi.as_ref()
+        .and_then(abi::methods)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} has no ABI", name)))?;
+    Ok(Json(methods))
+}
+
+/// The solc Standard JSON input `name` was compiled from, e.g. for
+/// verifying it on a block explorer.
+async fn contract_solc_input(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let contract = state.compile.contract(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?;
+    let compile = state.compile.clone();
+    let root = state.root_dir.clone();
+    let file = contract.source_file().map(str::to_string);
+    let run = tokio::task::spawn_blocking(move || compile.solc_input(root, &contract))
+        .await
+        .map_err(|e| ApiError::Internal(format!("Compile task failed: {}", e)))?
+        .map_err(|e| ApiError::Upstream(format!("Compilation failed: {}", e)))?
+        .ok_or_else(|| ApiError::NotFound(format!("No solc run compiles {} any more; recompile first", name)))?;
+    Ok(Json(serde_json::json!({
+        "contract": name,
+        "file": file,
+        "solcVersion": run.solc_version,
+        "solcLongVersion": run.solc_long_version,
+        "input": run.input,
+    })))
+}
+
+/// Calls `function` on the deployed `name` at `address`: an `eth_call` for
+/// view and pure functions, otherwise a transaction signed with `privateKey`.
+async fn contract_call(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<ContractCallRequest>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = payload.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    rpc::transport(&rpc_url)?;
+    let to: ethers::types::Address = payload.address.parse()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid address {}", payload.address)))?;
+    let abi = contract_abi(&state, &name)?;
+    let function = interact::find_function(&abi, &payload.function, payload.args.len())?;
+    let data = interact::encode(function, &payload.args)?;
+    let signature = abi::function_signature(function);
+
+    if matches!(function.state_mutability, ethers::abi::StateMutability::View | ethers::abi::StateMutability::Pure) {
+        let call = serde_json::json!({ "to": to, "data": format!("0x{}", ethers::utils::hex::encode(&data)) });
+        let result = rpc::call(&state.http, &rpc_url, "eth_call", serde_json::json!([call, "latest"])).await
+            .map_err(|e| e.into_api_error(&state.compile.abis()))?;
+        let output = result.as_str().and_then(abi::parse_hex)
+            .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_call result {}", result)))?;
+        let decoded = function.decode_output(&output)
+            .map_err(|e| ApiError::Upstream(format!("Failed to decode {} output: {}", signature, e)))?;
+        return Ok(Json(serde_json::json!({
+            "kind": "read",
+            "function": signature,
+            "outputs": decoded.iter().map(abi::token_to_json).collect::<Vec<_>>(),
+        })));
+    }
+
+    let key = payload.privateKey
+        .ok_or_else(|| ApiError::BadRequest(format!("{} changes state; a privateKey is needed to send it", signature)))?;
+    let value = match payload.value.as_deref() {
+        None => U256::zero(),
+        Some(v) => match v.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_dec_str(v).ok(),
+        }.ok_or_else(|| ApiError::BadRequest(format!("Invalid value {}", v)))?,
+    };
+    tracing::info!("Sending {} to {} on {}", signature, to, rpc_url);
+    let (hash, receipt) = interact::send(&state.http, &rpc_url, &key, to, data, value).await?;
+    Ok(Json(serde_json::json!({
+        "kind": "write",
+        "function": signature,
+        "txHash": hash,
+        "receipt": if receipt.is_null() { receipt } else { describe_receipt(&state, &receipt) },
+    })))
+}
+
+async fn contract_outline(
+    Path(name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let contract = state.compile.contract(&name)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} not found in the last compile", name)))?;
+    let ast = contract.ast
+        .ok_or_else(|| ApiError::NotFound(format!("No AST available for {}; wait for the next compile", name)))?;
+    let source = std::fs::read_to_string(state.root_dir.join(&ast.absolute_path)).ok();
+    Ok(Json(serde_json::json!({
+        "contract": name,
+        "file": ast.absolute_path,
+        "symbols": outline::symbols(&ast, source.as_deref()),
+    })))
+}
+
+const OPENCHAIN_LOOKUP_URL: &str = "https://api.openchain.xyz/signature-database/v1/lookup";
+
+async fn decode_selector(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DecodeSelectorRequest>,
+) -> ApiResult<serde_json::Value> {
+    let calldata = match &payload.calldata {
+        Some(data) => Some(abi::parse_hex(data)
+            .ok_or_else(|| ApiError::BadRequest("calldata is not valid hex".to_string()))?),
+        None => None,
+    };
+    let selector_bytes = match (&payload.selector, &calldata) {
+        (Some(sel), _) => abi::parse_hex(sel).filter(|b| b.len() == 4)
+            .ok_or_else(|| ApiError::BadRequest(format!("Invalid 4-byte selector: {}", sel)))?,
+        (None, Some(data)) if data.len() >= 4 => data[..4].to_vec(),
+        _ => return Err(ApiError::BadRequest("Provide a selector or at least 4 bytes of calldata".to_string())),
+    };
+    let selector = format!("0x{}", ethers::utils::hex::encode(&selector_bytes));
+
+    let mut candidates: Vec<SelectorCandidate> = Vec::new();
+    for contract in state.compile.contracts.read().unwrap().iter() {
+        let Some(contract_abi) = contract.ethers_abi() else { continue };
+        for f in contract_abi.functions().filter(|f| f.short_signature()[..] == selector_bytes[..]) {
+            candidates.push(SelectorCandidate {
+                signature: abi::function_signature(f),
+                source: "local",
+                contract: Some(contract.name.clone()),
+            });
         }
     }
 
-    // Start File Watcher
-    let tx_for_watcher = tx.clone();
-    let last_msg_for_watcher = last_msg.clone();
-    if let Err(e) = watcher::setup_watcher(root_dir.clone(), tx_for_watcher, last_msg_for_watcher).await {
-        tracing::error!("Failed to setup watcher: {}", e);
+    if payload.external {
+        let res = state.http.get(OPENCHAIN_LOOKUP_URL)
+            .query(&[("function", selector.as_str()), ("filter", "true")])
+            .send().await
+            .map_err(|e| ApiError::Upstream(format!("Signature lookup failed: {}", e)))?;
+        let body: serde_json::Value = res.json().await
+            .map_err(|e| ApiError::Upstream(format!("Invalid signature lookup response: {}", e)))?;
+        let matches = body.pointer(&format!("/result/function/{}", selector))
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+        for entry in matches {
+            if let Some(name) = entry.get("name").and_then(|n| n.as_str()) {
+                if !candidates.iter().any(|c| c.signature == name) {
+                    candidates.push(SelectorCandidate { signature: name.to_string(), source: "openchain", contract: None });
+                }
+            }
+        }
     }
 
-    let app_state = Arc::new(AppState { tx, last_msg, fork_node, root_dir });
+    let decoded = match (&payload.signature, &calldata) {
+        (Some(sig), Some(data)) => {
+            let f = abi::parse_function(sig)
+                .ok_or_else(|| ApiError::BadRequest(format!("Invalid function signature: {}", sig)))?;
+            let args = abi::decode_call(&f, data)
+                .ok_or_else(|| ApiError::BadRequest(format!("Calldata does not decode as {}", sig)))?;
+            Some(serde_json::json!({ "signature": abi::function_signature(&f), "args": args }))
+        }
+        _ => None,
+    };
 
-    // Build our application with a route
-    let app = Router::new()
-        .route("/ws", get(ws_handler))
-        .route("/inspect/:contract", get(inspect_storage))
-        .route("/trace/:tx_hash", get(get_trace))
-        .route("/trace/calltree", post(get_trace_calltree))
-        .route("/trace/call", post(get_trace_call))
-        .route("/fork/start", post(start_fork))
-        .route("/fork/stop", post(stop_fork))
-        .route("/fork/status", get(fork_status))
-        .route("/keystores", get(list_keystores))
-        .route("/keystores/unlock", post(unlock_keystore))
-        .route("/keystores/create", post(create_keystore))
-        .route("/keystores/remove", post(remove_keystore))
-        .route("/proxy", post(handle_proxy_request))
-        .route("/", get(serve_ui_root))
-        .route("/*path", get(serve_ui))
-        .layer(CorsLayer::permissive())
-        .with_state(app_state);
+    Ok(Json(serde_json::json!({
+        "selector": selector,
+        "candidates": candidates,
+        "decoded": decoded,
+    })))
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::info!("ChainSmith UI listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+async fn decode_logs(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DecodeLogsRequest>,
+) -> ApiResult<serde_json::Value> {
+    let abis = match &payload.contract {
+        Some(name) => vec![(name.clone(), contract_abi(&state, name)?)],
+        None => state.compile.abis(),
+    };
+    let logs: Vec<serde_json::Value> = payload.logs.iter().map(|log| abi::decode_log(&abis, log)).collect();
+    Ok(Json(serde_json::json!({ "logs": logs })))
 }
 
-async fn serve_ui_root() -> Response {
-    serve_ui(Path("".to_string())).await
+async fn get_receipt(
+    Path(hash): Path<String>,
+    Query(params): Query<RpcUrlParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = params.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    let receipt = rpc::call(&state.http, &rpc_url, "eth_getTransactionReceipt", serde_json::json!([hash])).await?;
+    if receipt.is_null() {
+        return Err(ApiError::NotFound(format!("No receipt for {} (unknown or still pending)", hash)));
+    }
+    Ok(Json(describe_receipt(&state, &receipt)))
 }
 
-async fn ws_handler(
-    ws: WebSocketUpgrade,
-    axum::extract::State(state): axum::extract::State<Arc<AppState>>,
-) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+/// A receipt's main fields with its logs decoded against the compiled ABIs.
+fn describe_receipt(state: &AppState, receipt: &serde_json::Value) -> serde_json::Value {
+    let logs: Vec<abi::LogInput> = receipt.get("logs").cloned()
+        .and_then(|logs| serde_json::from_value(logs).ok())
+        .unwrap_or_default();
+    let abis = state.compile.abis();
+    let decoded_logs: Vec<serde_json::Value> = logs.iter().map(|log| abi::decode_log(&abis, log)).collect();
+
+    let field = |name: &str| receipt.get(name).cloned().unwrap_or(serde_json::Value::Null);
+    let number = |name: &str| receipt.get(name).and_then(rpc::quantity).map(|v| v.to_string());
+    serde_json::json!({
+        "transactionHash": field("transactionHash"),
+        "status": receipt.get("status").and_then(rpc::quantity).map(|s| if s.is_zero() { "reverted" } else { "success" }),
+        "blockNumber": number("blockNumber"),
+        "from": field("from"),
+        "to": field("to"),
+        "contractAddress": field("contractAddress"),
+        "gasUsed": number("gasUsed"),
+        "effectiveGasPrice": number("effectiveGasPrice"),
+        "logs": decoded_logs,
+    })
 }
 
-async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>) {
-    let cached_msg = {
-        let lock = state.last_msg.lock().unwrap();
-        lock.clone()
-    };
+/// Flattens txpool_content's `{ sender: { nonce: tx } }` maps into a list.
+fn normalize_pool(pool: Option<&serde_json::Value>) -> Vec<serde_json::Value> {
+    let Some(senders) = pool.and_then(|p| p.as_object()) else { return Vec::new() };
+    let mut txs: Vec<serde_json::Value> = senders.values()
+        .filter_map(|by_nonce| by_nonce.as_object())
+        .flat_map(|by_nonce| by_nonce.values())
+        .map(|tx| serde_json::json!({
+            "hash": tx.get("hash"),
+            "from": tx.get("from"),
+            "to": tx.get("to"),
+            "nonce": tx.get("nonce").and_then(rpc::quantity).and_then(|v| u64::try_from(v).ok()),
+            "value": tx.get("value").and_then(rpc::quantity).map(|v| v.to_string()),
+            "gas": tx.get("gas").and_then(rpc::quantity).map(|v| v.to_string()),
+        }))
+        .collect();
+    txs.sort_by_key(|tx| (tx["from"].as_str().map(str::to_lowercase), tx["nonce"].as_u64()));
+    txs
+}
 
-    if let Some(msg) = cached_msg {
-        let _ = socket.send(Message::Text(msg)).await;
+async fn anvil_txpool(State(state): State<Arc<AppState>>) -> ApiResult<serde_json::Value> {
+    let url = state.primary_node.lock().unwrap().url();
+    match rpc::call(&state.http, &url, "txpool_content", serde_json::json!([])).await {
+        Ok(content) => Ok(Json(serde_json::json!({
+            "supported": true,
+            "pending": normalize_pool(content.get("pending")),
+            "queued": normalize_pool(content.get("queued")),
+        }))),
+        Err(e) if e.is_method_not_found() => Ok(Json(serde_json::json!({
+            "supported": false,
+            "pending": [],
+            "queued": [],
+        }))),
+        Err(e) => Err(e.into()),
     }
+}
 
-    let mut rx = state.tx.subscribe();
-
-    while let Ok(msg) = rx.recv().await {
-        if socket.send(Message::Text(msg)).await.is_err() {
-            break;
-        }
+/// The primary node's accounts with their balances. Anvil's default dev
+/// accounts come with their private keys.
+async fn anvil_accounts(State(state): State<Arc<AppState>>) -> ApiResult<Vec<serde_json::Value>> {
+    let url = state.primary_node.lock().unwrap().url();
+    let accounts = rpc::call(&state.http, &url, "eth_accounts", serde_json::json!([])).await?;
+    let addresses: Vec<Address> = serde_json::from_value(accounts.clone())
+        .map_err(|_| ApiError::Upstream(format!("Unexpected eth_accounts result {}", accounts)))?;
+
+    let mut listed = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        let balance = rpc::call(&state.http, &url, "eth_getBalance", serde_json::json!([address, "latest"])).await?;
+        let balance = rpc::quantity(&balance)
+            .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_getBalance result {}", balance)))?;
+        let private_key = anvil::dev_accounts().iter().find(|(dev, _)| *dev == address).map(|(_, key)| key);
+        listed.push(serde_json::json!({
+            "address": address,
+            "balance": balance.to_string(),
+            "balanceEther": ethers::utils::format_ether(balance),
+            "privateKey": private_key,
+        }));
     }
+    Ok(Json(listed))
 }
 
-async fn inspect_storage(
-    Path(contract): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> Response {
-    tracing::info!("Inspecting storage for {}", contract);
-
-    let current_dir = state.root_dir.clone();
-    let mut file_path = None;
-
-    // Determine source directory
-    let contracts_dir = current_dir.join("contracts");
-    let src_path = if contracts_dir.exists() {
-        contracts_dir.clone()
-    } else {
-        current_dir.clone()
+/// An amount given as a JSON string or number in `unit` (`wei`, `gwei`,
+/// `ether`, ... or a number of decimals), in wei. Parsed as decimal text,
+/// so nothing is lost to floating point.
+fn parse_amount(amount: &serde_json::Value, unit: &str) -> Result<U256, ApiError> {
+    let amount = match amount {
+        serde_json::Value::String(s) => s.trim().to_string(),
+        serde_json::Value::Number(n) => n.to_string(),
+        other => return Err(ApiError::BadRequest(format!("Invalid amount {}", other))),
     };
-
-    for entry in WalkDir::new(&current_dir).into_iter().filter_map(|e| e.ok()) {
-        if entry.file_name().to_string_lossy() == format!("{}.sol", contract) {
-            file_path = Some(entry.path().to_path_buf());
-            break;
-        }
+    // parse_units drops digits past the unit's precision instead of failing
+    let decimals = ethers::utils::Units::try_from(unit)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid unit {}: {}", unit, e)))?
+        .as_num();
+    let fraction = amount.split_once('.').map_or("", |(_, f)| f).trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(ApiError::BadRequest(format!("{} {} isn't a whole number of wei", amount, unit)));
     }
+    match ethers::utils::parse_units(&amount, unit) {
+        Ok(ethers::utils::ParseUnits::U256(wei)) => Ok(wei),
+        Ok(ethers::utils::ParseUnits::I256(_)) => Err(ApiError::BadRequest(format!("Amount {} is negative", amount))),
+        Err(e) => Err(ApiError::BadRequest(format!("Invalid amount {} {}: {}", amount, unit, e))),
+    }
+}
 
-    let target = if let Some(path) = file_path {
-        format!("{}:{}", path.display(), contract)
-    } else {
-        contract
+/// Converts an amount between units, e.g. gwei to ether.
+async fn convert_units(Json(payload): Json<UnitsRequest>) -> ApiResult<serde_json::Value> {
+    let wei = parse_amount(&payload.value, &payload.from)?;
+    let converted = ethers::utils::format_units(wei, payload.to.as_str())
+        .map_err(|e| ApiError::BadRequest(format!("Invalid unit {}: {}", payload.to, e)))?;
+    // format_units pads to the unit's decimals; 1.500000000000000000 reads as 1.5
+    let converted = match converted.split_once('.') {
+        Some((whole, fraction)) if fraction.trim_end_matches('0').is_empty() => whole.to_string(),
+        Some(_) => converted.trim_end_matches('0').to_string(),
+        None => converted,
     };
+    Ok(Json(serde_json::json!({ "value": converted, "wei": wei.to_string() })))
+}
 
-    let output = Command::new("forge")
-        .arg("inspect")
-        .arg(&target)
-        .arg("storage")
-        .arg("--json")
-        .arg("--root")
-        .arg(&current_dir)
-        .arg("--contracts")
-        .arg(&src_path)
-        .output();
-
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                Json(serde_json::from_str::<serde_json::Value>(&stdout).unwrap_or(serde_json::json!({
-                    "error": "Failed to parse forge output"
-                }))).into_response()
-            } else {
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                Json(serde_json::json!({
-                    "error": format!("Forge failed: {}", stderr)
-                })).into_response()
+/// Sets `address`'s balance on the primary node with `anvil_setBalance`.
+async fn anvil_fund(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<FundRequest>,
+) -> ApiResult<serde_json::Value> {
+    let address: Address = payload.address.parse()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid address {}", payload.address)))?;
+    let wei = parse_amount(&payload.amount, payload.unit.as_deref().unwrap_or("ether"))?;
+
+    let url = state.primary_node.lock().unwrap().url();
+    tracing::info!("Setting the balance of {} to {} wei", address, wei);
+    rpc::call(&state.http, &url, "anvil_setBalance", serde_json::json!([address, wei])).await?;
+    let balance = rpc::call(&state.http, &url, "eth_getBalance", serde_json::json!([address, "latest"])).await?;
+    let balance = rpc::quantity(&balance)
+        .ok_or_else(|| ApiError::Upstream(format!("Unexpected eth_getBalance result {}", balance)))?;
+    Ok(Json(serde_json::json!({
+        "address": address,
+        "balance": balance.to_string(),
+        "balanceEther": ethers::utils::format_ether(balance),
+    })))
+}
+
+/// The node a trace request runs on: the built-in one `target` names,
+/// which has to be running, or else `rpc_url`, or else the default node.
+fn trace_rpc_url(state: &AppState, rpc_url: Option<String>, target: Option<NodeTarget>) -> Result<String, ApiError> {
+    let url = match (target, rpc_url) {
+        (Some(_), Some(_)) => return Err(ApiError::BadRequest("Give either rpcUrl or target, not both".to_string())),
+        (Some(NodeTarget::Local), None) => {
+            let node = state.primary_node.lock().unwrap();
+            if !node.is_running() {
+                return Err(ApiError::BadRequest("The local anvil node isn't running".to_string()));
             }
-        },
-        Err(e) => {
-             Json(serde_json::json!({
-                "error": format!("Failed to execute forge: {}", e)
-            })).into_response()
+            node.url()
         }
-    }
+        (Some(NodeTarget::Fork), None) => {
+            let node = state.fork_node.lock().unwrap();
+            if !node.is_running() {
+                return Err(ApiError::BadRequest("No fork is running".to_string()));
+            }
+            node.url()
+        }
+        (None, url) => url.unwrap_or_else(|| state.rpc_url.clone()),
+    };
+    rpc::transport(&url)?;
+    Ok(url)
 }
 
 async fn get_trace(
     Path(tx_hash): Path<String>,
     Query(params): Query<TraceParams>,
     State(state): State<Arc<AppState>>,
-) -> Response {
-    let rpc_url = params.rpc_url.unwrap_or("http://127.0.0.1:8545".to_string());
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = trace_rpc_url(&state, params.rpc_url, params.target)?;
+    let extra = extra_args::from_query(params.extra_args.as_deref());
+    run_trace(&state, &tx_hash, &rpc_url, &extra)
+}
+
+/// Runs `cast run` on `tx_hash`, recording it in the trace history if it
+/// succeeds.
+fn run_trace(state: &AppState, tx_hash: &str, rpc_url: &str, extra: &[String]) -> ApiResult<serde_json::Value> {
+    rpc::transport(rpc_url)?;
+    let extra = extra_args::validate(extra, extra_args::CastCommand::Run)?;
     tracing::info!("Tracing tx {} on {}", tx_hash, rpc_url);
 
     // cast run <tx> --rpc-url <url>
     // cast run outputs colored ansi. We want that to display in frontend.
-    let output = Command::new("cast")
+    let output = run_timed(state, Command::new("cast")
+        .current_dir(&state.root_dir)
+        .arg("run")
+        .arg(tx_hash)
+        .arg("--rpc-url")
+        .arg(rpc_url)
+        .args(extra))?;
+    if output.status.success() {
+        state.trace_history.record(tx_hash, rpc_url, extra);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // cast run output is often in stdout, but errors in stderr.
+    // We return both, plus the console.log calls pulled out of the trace.
+    Ok(Json(serde_json::json!({
+        "stdout": stdout,
+        "stderr": stderr,
+        "exitCode": output.status.code(),
+        "logs": console::logs(&stdout),
+    })))
+}
+
+/// Traces the transaction at `index` in block `number` (decimal or 0x hex).
+async fn trace_by_position(
+    Path((number, index)): Path<(String, String)>,
+    Query(params): Query<TraceParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let parse = |value: &str, what: &str| match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse::<u64>().ok(),
+    }.ok_or_else(|| ApiError::BadRequest(format!("Invalid {} {}", what, value)));
+    let (number, index) = (parse(&number, "block number")?, parse(&index, "transaction index")?);
+    let rpc_url = trace_rpc_url(&state, params.rpc_url, params.target)?;
+
+    let tx = rpc::call(
+        &state.http,
+        &rpc_url,
+        "eth_getTransactionByBlockNumberAndIndex",
+        serde_json::json!([format!("{:#x}", number), format!("{:#x}", index)]),
+    ).await?;
+    let hash = tx.get("hash").and_then(|h| h.as_str())
+        .ok_or_else(|| ApiError::NotFound(format!("No transaction {} in block {}", index, number)))?
+        .to_string();
+    let extra = extra_args::from_query(params.extra_args.as_deref());
+    let Json(mut trace) = run_trace(&state, &hash, &rpc_url, &extra)?;
+    trace["hash"] = hash.into();
+    Ok(Json(trace))
+}
+
+async fn list_trace_history(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "entries": state.trace_history.list(),
+        "max": history::MAX_ENTRIES,
+    }))
+}
+
+async fn clear_trace_history(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    state.trace_history.clear();
+    Json(serde_json::json!({ "status": "success" }))
+}
+
+/// Traces a history entry again, as `/trace/:hash` would.
+async fn replay_trace(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let entry = state.trace_history.get(id)
+        .ok_or_else(|| ApiError::NotFound(format!("No trace history entry {}", id)))?;
+    run_trace(&state, &entry.hash, &entry.rpc_url, &entry.extra_args)
+}
+
+/// Like `get_trace`, but returns as soon as `cast run` starts and pushes its
+/// output over the WebSocket line by line (`trace_line`), followed by a
+/// `trace_done` once the process exits.
+async fn stream_trace(
+    Path(tx_hash): Path<String>,
+    Query(params): Query<TraceParams>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = trace_rpc_url(&state, params.rpc_url, params.target)?;
+    let extra = extra_args::from_query(params.extra_args.as_deref());
+    tracing::info!("Streaming trace of tx {} on {}", tx_hash, rpc_url);
+
+    // Held by the task below until cast exits
+    let permit = command_permit(&state)?;
+    let mut child = tokio::process::Command::new("cast")
         .current_dir(&state.root_dir)
         .arg("run")
         .arg(&tx_hash)
         .arg("--rpc-url")
         .arg(&rpc_url)
-        .output();
-
-    match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            let stderr = String::from_utf8_lossy(&out.stderr);
-            
-            // cast run output is often in stdout, but errors in stderr.
-            // We return both.
-            Json(serde_json::json!({
-                "stdout": stdout,
-                "stderr": stderr
-            })).into_response()
-        },
-        Err(e) => {
-             Json(serde_json::json!({
-                "error": format!("Failed to execute cast: {}", e)
-            })).into_response()
+        .args(extra_args::validate(&extra, extra_args::CastCommand::Run)?)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| ApiError::spawn_failed("cast", e))?;
+
+    let stdout = child.stdout.take().map(|out| forward_lines(state.tx.clone(), tx_hash.clone(), "stdout", out));
+    let stderr = child.stderr.take().map(|err| forward_lines(state.tx.clone(), tx_hash.clone(), "stderr", err));
+
+    let tx = state.tx.clone();
+    let hash = tx_hash.clone();
+    state.metrics.record_subprocess("cast");
+    let span = tracing::info_span!("subprocess", tool = "cast", subcommand = "run");
+    let started = Instant::now();
+    tokio::spawn(async move {
+        // Drain both pipes before reporting completion so no lines trail the done message
+        for task in [stdout, stderr].into_iter().flatten() {
+            let _ = task.await;
+        }
+        let (success, status) = match child.wait().await {
+            Ok(status) => (status.success(), status.to_string()),
+            Err(e) => (false, format!("Failed to wait for cast: {}", e)),
+        };
+        drop(permit);
+        tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, %status, "finished");
+        ws::broadcast(&tx, &ws::WsMessage::TraceDone { hash, success, status });
+    }.instrument(span));
+
+    Ok(Json(serde_json::json!({ "started": true, "hash": tx_hash })))
+}
+
+/// Claims one of the `--max-concurrent-commands` slots, held until the
+/// permit is dropped. Excess requests are refused rather than queued, since
+/// a client that bursts them is better told to back off than left waiting.
+fn command_permit(state: &AppState) -> Result<tokio::sync::OwnedSemaphorePermit, ApiError> {
+    state.commands.clone().try_acquire_owned().map_err(|_| {
+        state.metrics.record_rejected_subprocess();
+        ApiError::TooManyRequests("Too many commands are already running; try again shortly".to_string())
+    })
+}
+
+/// Runs `cmd` to completion inside a `subprocess` span that logs how long
+/// it took. Only the program and subcommand are recorded, never the rest of
+/// the arguments, which can hold keystore passwords.
+fn run_timed(state: &AppState, cmd: &mut Command) -> Result<std::process::Output, ApiError> {
+    let _permit = command_permit(state)?;
+    let tool = cmd.get_program().to_string_lossy().to_string();
+    state.metrics.record_subprocess(&tool);
+    let subcommand = cmd.get_args().next().map(|a| a.to_string_lossy().to_string()).unwrap_or_default();
+    let _span = tracing::info_span!("subprocess", tool = %tool, subcommand = %subcommand).entered();
+    let started = Instant::now();
+    let output = cmd.output().map_err(|e| ApiError::spawn_failed(&tool, e))?;
+    tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, status = %output.status, "finished");
+    Ok(output)
+}
+
+fn forward_lines<R: tokio::io::AsyncRead + Unpin + Send + 'static>(
+    tx: broadcast::Sender<ws::Broadcast>,
+    hash: String,
+    stream: &'static str,
+    reader: R,
+) -> tokio::task::JoinHandle<()> {
+    use tokio::io::AsyncBufReadExt;
+    tokio::spawn(async move {
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            ws::broadcast(&tx, &ws::WsMessage::TraceLine { hash: hash.clone(), stream, line });
+        }
+    })
+}
+
+/// Resolves program counters in `contract`'s deployed code to source
+/// ranges. Entries are `null` for PCs without a mapping.
+async fn debug_resolve(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DebugResolveRequest>,
+) -> ApiResult<serde_json::Value> {
+    let pcs: Vec<usize> = payload.pc.into_iter().chain(payload.pcs.unwrap_or_default()).collect();
+    if pcs.is_empty() {
+        return Err(ApiError::BadRequest("Either pc or pcs is required".to_string()));
+    }
+    if state.compile.contract(&payload.contract).is_none() {
+        return Err(ApiError::NotFound(format!("Contract {} not found in the last compile", payload.contract)));
+    }
+    let map = state.compile.source_map(&payload.contract)
+        .ok_or_else(|| ApiError::NotFound(format!("Contract {} has no deployed source map", payload.contract)))?;
+
+    let mut files = debug::SourceFiles::new(state.root_dir.clone());
+    let locations: Vec<_> = pcs.iter().map(|&pc| files.locate(&map, pc)).collect();
+    Ok(Json(serde_json::json!({ "contract": payload.contract, "locations": locations })))
+}
+
+async fn debug_steps(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DebugStepsRequest>,
+) -> ApiResult<debug::StepTrace> {
+    let rpc_url = payload.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    let trace = debug::trace_steps(&state.http, &rpc_url, &payload.txHash, payload.capture).await?;
+    Ok(Json(trace))
+}
+
+/// Starts a stepping session over `trace`, or over the trace of `txHash` if
+/// no trace is given.
+async fn create_debug_session(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DebugSessionRequest>,
+) -> ApiResult<serde_json::Value> {
+    let trace = match (payload.trace, &payload.txHash) {
+        (Some(trace), _) => trace,
+        (None, Some(hash)) => {
+            let rpc_url = payload.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+            debug::trace_steps(&state.http, &rpc_url, hash, payload.capture).await?
         }
+        (None, None) => return Err(ApiError::BadRequest("Either trace or txHash is required".to_string())),
+    };
+    let map = match &payload.contract {
+        Some(name) => Some(state.compile.source_map(name)
+            .ok_or_else(|| ApiError::NotFound(format!("No deployed source map for {} in the last compile", name)))?),
+        None => None,
+    };
+    let contract = payload.contract.as_deref().and_then(|name| state.compile.contract(name));
+
+    let session = debug::Session::new(trace, map, contract.as_ref(), state.root_dir.clone());
+    let id = state.debug_sessions.lock().unwrap().insert(session);
+    with_debug_session(&state, id, |_| {})
+}
+
+async fn delete_debug_session(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    if !state.debug_sessions.lock().unwrap().remove(id) {
+        return Err(ApiError::NotFound(format!("Debug session {} not found", id)));
     }
+    Ok(Json(serde_json::json!({ "status": "success" })))
+}
+
+async fn step_debug_session(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+    payload: Option<Json<DebugStepRequest>>,
+) -> ApiResult<serde_json::Value> {
+    let by_line = payload.is_some_and(|Json(p)| p.by == "line");
+    with_debug_session(&state, id, |session| {
+        if by_line { session.step_line() } else { session.step_opcode() }
+    })
+}
+
+async fn continue_debug_session(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<serde_json::Value> {
+    with_debug_session(&state, id, debug::Session::resume)
+}
+
+/// Replaces the session's breakpoints.
+async fn set_debug_breakpoints(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<DebugBreakpointsRequest>,
+) -> ApiResult<serde_json::Value> {
+    with_debug_session(&state, id, |session| session.set_breakpoints(payload.breakpoints))
+}
+
+async fn debug_session_locals(
+    Path(id): Path<u64>,
+    State(state): State<Arc<AppState>>,
+) -> ApiResult<locals::Locals> {
+    let sessions = state.debug_sessions.lock().unwrap();
+    let session = sessions.get(id)
+        .ok_or_else(|| ApiError::NotFound(format!("Debug session {} not found", id)))?;
+    session.locals().map(Json).map_err(|e| ApiError::BadRequest(e.to_string()))
+}
+
+fn with_debug_session(
+    state: &AppState,
+    id: u64,
+    advance: impl FnOnce(&mut debug::Session),
+) -> ApiResult<serde_json::Value> {
+    let mut sessions = state.debug_sessions.lock().unwrap();
+    let session = sessions.get_mut(id)
+        .ok_or_else(|| ApiError::NotFound(format!("Debug session {} not found", id)))?;
+    advance(session);
+    Ok(Json(serde_json::json!(session.state(id))))
 }
 
 async fn get_trace_call(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<TraceCallRequest>,
-) -> Response {
-    let url = payload.rpcUrl;
+) -> ApiResult<serde_json::Value> {
+    let url = trace_rpc_url(&state, payload.rpcUrl, payload.target)?;
     let block_tag = payload.blockTag.unwrap_or("latest".to_string());
+    let state_overrides = overrides::validate(&payload.stateOverrides)?;
 
-    let body = serde_json::json!({
-        "jsonrpc": "2.0",
-        "id": 1,
-        "method": "debug_traceCall",
-        "params": [payload.call, block_tag]
-    });
+    let mut params = vec![payload.call, serde_json::json!(block_tag)];
+    if !state_overrides.is_empty() {
+        params.push(overrides::to_trace_config(&state_overrides));
+    }
 
-    let output = Command::new("curl")
-        .current_dir(&state.root_dir)
-        .arg("-sS")
-        .arg("-X")
-        .arg("POST")
-        .arg(&url)
-        .arg("-H")
-        .arg("Content-Type: application/json")
-        .arg("-d")
-        .arg(body.to_string())
-        .output();
-
-    match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            let stderr = String::from_utf8_lossy(&out.stderr);
-            if !out.status.success() {
-                return Json(serde_json::json!({
-                    "error": format!("Trace call failed: {}", stderr)
-                })).into_response();
-            }
-            if stdout.trim().is_empty() {
-                return Json(serde_json::json!({
-                    "error": format!("Empty trace response: {}", stderr)
-                })).into_response();
-            }
-            Json(serde_json::json!({
-                "stdout": stdout,
-                "stderr": stderr
-            })).into_response()
-        }
-        Err(e) => {
-            Json(serde_json::json!({
-                "error": format!("Failed to execute trace call: {}", e)
-            })).into_response()
-        }
+    // `stdout` stays the raw JSON-RPC response it was when this ran curl
+    let response = rpc::call_response(&state.http, &url, "debug_traceCall", serde_json::json!(params), 1).await
+        .map_err(|e| ApiError::Upstream(format!("Trace call failed: {}", e)))?;
+    // The struct logger reports a failed call's revert data as its return value
+    let result = &response["result"];
+    let revert = (result["failed"] == true)
+        .then(|| result["returnValue"].as_str().and_then(abi::parse_hex))
+        .flatten()
+        .map(|data| abi::decode_revert(&state.compile.abis(), &data));
+    Ok(Json(serde_json::json!({
+        "stdout": response.to_string(),
+        "stderr": "",
+        "revert": revert,
+    })))
+}
+
+/// Runs a call through the prestate tracer in diff mode and reports the
+/// balances, nonces, code and storage it would change.
+async fn simulate_call(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<SimulateRequest>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = payload.rpcUrl.unwrap_or_else(|| state.rpc_url.clone());
+    rpc::transport(&rpc_url)?;
+    let mut call = serde_json::json!({ "to": payload.to, "data": payload.data.unwrap_or_else(|| "0x".to_string()) });
+    if let Some(from) = payload.from {
+        call["from"] = from.into();
+    }
+    if let Some(value) = payload.value {
+        call["value"] = value;
     }
+    let block_tag = payload.blockTag.unwrap_or("latest".to_string());
+    let config = serde_json::json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } });
+
+    let result = rpc::call(&state.http, &rpc_url, "debug_traceCall", serde_json::json!([call, block_tag, config]))
+        .await
+        .map_err(|e| if e.is_method_not_found() {
+            ApiError::Upstream(format!("{} doesn't support debug_traceCall, which /simulate needs", rpc_url))
+        } else {
+            e.into()
+        })?;
+
+    let contracts = state.compile.contracts.read().unwrap();
+    let named: HashMap<String, &compiler::ContractData> = payload.contracts.iter()
+        .filter_map(|(address, name)| Some((address.to_lowercase(), contracts.iter().find(|c| &c.name == name)?)))
+        .collect();
+    let accounts = simulate::changes(&result, |address, code| {
+        named.get(&address.to_lowercase()).copied().or_else(|| simulate::identify(&contracts, code?))
+    });
+    Ok(Json(serde_json::json!({ "accounts": accounts })))
+}
+
+/// Traces a call with `debug_traceCall`'s `callTracer` and decodes each
+/// frame against the compiled ABIs.
+async fn decoded_trace_calltree(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<TraceCallRequest>,
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = trace_rpc_url(&state, payload.rpcUrl, payload.target)?;
+    let block_tag = payload.blockTag.unwrap_or("latest".to_string());
+    let state_overrides = overrides::validate(&payload.stateOverrides)?;
+
+    let mut config = if state_overrides.is_empty() {
+        serde_json::json!({})
+    } else {
+        overrides::to_trace_config(&state_overrides)
+    };
+    config["tracer"] = "callTracer".into();
+    let result = rpc::call(&state.http, &rpc_url, "debug_traceCall", serde_json::json!([payload.call, block_tag, config]))
+        .await
+        .map_err(|e| if e.is_method_not_found() {
+            ApiError::Upstream(format!("{} doesn't support debug_traceCall, which /trace/calltree/decoded needs", rpc_url))
+        } else {
+            e.into()
+        })?;
+
+    let abis = state.compile.abis();
+    Ok(Json(serde_json::json!({ "root": calltree::decode(&result, &abis) })))
 }
 
 async fn get_trace_calltree(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<TraceCalltreeRequest>,
-) -> Response {
-    let rpc_url = payload.rpcUrl;
+) -> ApiResult<serde_json::Value> {
+    let rpc_url = trace_rpc_url(&state, payload.rpcUrl, payload.target)?;
     let block_tag = payload.blockTag.unwrap_or("latest".to_string());
+    let state_overrides = overrides::validate(&payload.stateOverrides)?;
 
     let to = payload.call.get("to").and_then(|v| v.as_str()).unwrap_or("");
     let data = payload.call.get("data").and_then(|v| v.as_str()).unwrap_or("0x");
     let value = payload.call.get("value").and_then(|v| v.as_str());
     let from = payload.call.get("from").and_then(|v| v.as_str());
     let gas = payload.call.get("gas").and_then(|v| v.as_str());
+    let create2_address = match &payload.create2 {
+        Some(create2) => Some(create2_address(create2, to, data)?),
+        None => None,
+    };
 
     let mut cmd = Command::new("cast");
     cmd.current_dir(&state.root_dir);
@@ -424,6 +2350,7 @@ async fn get_trace_calltree(
         }
     }
     cmd.arg("--block").arg(&block_tag);
+    cmd.args(extra_args::validate(&payload.extraArgs, extra_args::CastCommand::Call)?);
 
     if to.is_empty() {
         // Contract creation trace
@@ -448,98 +2375,386 @@ async fn get_trace_calltree(
         }
     }
 
-    let output = cmd.output();
+    // cast can't take overrides itself, so apply them to the node for the
+    // duration of the trace
+    let snapshot = if state_overrides.is_empty() {
+        None
+    } else {
+        Some(overrides::apply_on_anvil(&state.http, &rpc_url, &state_overrides).await?)
+    };
+    // Read after the overrides, which may set the sender's nonce
+    let created_address = match create2_address {
+        Some(address) => Ok(Some(address)),
+        None if to.is_empty() => create_address(&state, &rpc_url, from, &block_tag).await.map(Some),
+        None => Ok(None),
+    };
+    let output = run_timed(&state, &mut cmd);
+    if let Some(snapshot) = snapshot {
+        overrides::revert(&state.http, &rpc_url, snapshot).await;
+    }
+    let output = output?;
+    let created_address = created_address?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        let message = error::command_failed("Cast trace failed", &output);
+        return Err(match abi::revert_data_in(&stderr) {
+            Some(data) => ApiError::Reverted { message, revert: abi::decode_revert(&state.compile.abis(), &data) },
+            None => ApiError::Upstream(message),
+        });
+    }
+    if stdout.trim().is_empty() {
+        return Err(ApiError::Upstream(format!("Empty trace response: {}", stderr)));
+    }
+    Ok(Json(serde_json::json!({
+        "stdout": stdout,
+        "stderr": stderr,
+        "createdAddress": created_address.map(|a| ethers::utils::to_checksum(&a, None)),
+    })))
+}
+
+/// Where a creation from `from` (`cast`'s default sender, the zero address,
+/// if unset) would deploy: derived from the sender's nonce at `block_tag`.
+async fn create_address(state: &AppState, rpc_url: &str, from: Option<&str>, block_tag: &str) -> Result<Address, ApiError> {
+    let sender: Address = match from {
+        Some(from) => from.parse().map_err(|_| ApiError::BadRequest(format!("Invalid from address {}", from)))?,
+        None => Address::zero(),
+    };
+    let nonce = rpc::call(&state.http, rpc_url, "eth_getTransactionCount", serde_json::json!([sender, block_tag])).await?;
+    let nonce = rpc::quantity(&nonce)
+        .ok_or_else(|| ApiError::Upstream(format!("Unexpected nonce {}", nonce)))?;
+    Ok(ethers::utils::get_contract_address(sender, nonce))
+}
 
-    match output {
-        Ok(out) => {
-            let stdout = String::from_utf8_lossy(&out.stdout);
-            let stderr = String::from_utf8_lossy(&out.stderr);
-            if !out.status.success() {
-                return Json(serde_json::json!({
-                    "error": format!("Cast trace failed: {}", stderr)
-                })).into_response();
+/// Where the CREATE2 factory at `to` would deploy, from the salt and the
+/// init code (by default `data` after the salt).
+fn create2_address(create2: &Create2Request, to: &str, data: &str) -> Result<Address, ApiError> {
+    let factory: Address = to.parse()
+        .map_err(|_| ApiError::BadRequest("create2 needs the factory's address as call.to".to_string()))?;
+    let salt_word = parse_salt(&create2.salt)?;
+    let init_code = match &create2.initCode {
+        Some(code) => abi::parse_hex(code)
+            .ok_or_else(|| ApiError::BadRequest("create2.initCode is not valid hex".to_string()))?,
+        None => {
+            let data = abi::parse_hex(data)
+                .ok_or_else(|| ApiError::BadRequest("call.data is not valid hex".to_string()))?;
+            if data.len() < 32 || data[..32] != salt_word {
+                return Err(ApiError::BadRequest("call.data doesn't start with the salt; give create2.initCode".to_string()));
             }
-            if stdout.trim().is_empty() {
-                return Json(serde_json::json!({
-                    "error": format!("Empty trace response: {}", stderr)
-                })).into_response();
-            }
-            Json(serde_json::json!({
-                "stdout": stdout,
-                "stderr": stderr
-            })).into_response()
+            data[32..].to_vec()
         }
-        Err(e) => {
-            Json(serde_json::json!({
-                "error": format!("Failed to execute cast trace: {}", e)
-            })).into_response()
+    };
+    Ok(ethers::utils::get_create2_address(factory, salt_word, init_code))
+}
+
+/// A CREATE2 salt as its 32-byte word; shorter hex (odd lengths too) is
+/// left-padded, like a `uint256` salt.
+fn parse_salt(salt: &str) -> Result<[u8; 32], ApiError> {
+    let digits = salt.trim().trim_start_matches("0x");
+    let bytes = abi::parse_hex(&format!("{}{}", "0".repeat(digits.len() % 2), digits))
+        .ok_or_else(|| ApiError::BadRequest(format!("Salt {} is not valid hex", salt)))?;
+    if bytes.len() > 32 {
+        return Err(ApiError::BadRequest(format!("Salt is {} bytes; it can be at most 32", bytes.len())));
+    }
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(&bytes);
+    Ok(word)
+}
+
+/// Computes a CREATE2 address from the deployer, salt and init code (or
+/// its hash).
+async fn create2(Json(payload): Json<Create2AddressRequest>) -> ApiResult<serde_json::Value> {
+    let deployer: Address = payload.deployer.parse()
+        .map_err(|_| ApiError::BadRequest(format!("Invalid deployer address {}", payload.deployer)))?;
+    let salt = parse_salt(&payload.salt)?;
+    let init_code_hash = match (&payload.initCodeHash, &payload.initCode) {
+        (Some(hash), None) => {
+            let hash = abi::parse_hex(hash)
+                .ok_or_else(|| ApiError::BadRequest("initCodeHash is not valid hex".to_string()))?;
+            <[u8; 32]>::try_from(hash.as_slice())
+                .map_err(|_| ApiError::BadRequest(format!("initCodeHash is {} bytes; it must be 32", hash.len())))?
+        }
+        (None, Some(code)) => ethers::utils::keccak256(abi::parse_hex(code)
+            .ok_or_else(|| ApiError::BadRequest("initCode is not valid hex".to_string()))?),
+        _ => return Err(ApiError::BadRequest("Provide either initCodeHash or initCode".to_string())),
+    };
+    let address = ethers::utils::get_create2_address_from_hash(deployer, salt, init_code_hash);
+    Ok(Json(serde_json::json!({
+        "address": ethers::utils::to_checksum(&address, None),
+        "salt": format!("0x{}", ethers::utils::hex::encode(salt)),
+        "initCodeHash": format!("0x{}", ethers::utils::hex::encode(init_code_hash)),
+    })))
+}
+
+/// Checksums an address and says whether its casing was a valid EIP-55
+/// checksum (all lower or upper case counts, as it carries none).
+async fn check_address(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<AddressRequest>,
+) -> ApiResult<serde_json::Value> {
+    let input = payload.address.trim();
+    let digits = input.strip_prefix("0x").or_else(|| input.strip_prefix("0X")).unwrap_or(input);
+    let bytes = ethers::utils::hex::decode(digits)
+        .map_err(|_| ApiError::BadRequest(format!("{} is not valid hex", payload.address)))?;
+    if bytes.len() != 20 {
+        return Err(ApiError::BadRequest(format!("An address is 20 bytes; {} is {}", payload.address, bytes.len())));
+    }
+    let address = Address::from_slice(&bytes);
+    let checksummed = ethers::utils::to_checksum(&address, None);
+    let uniform_case = !digits.chars().any(|c| c.is_ascii_uppercase()) || !digits.chars().any(|c| c.is_ascii_lowercase());
+    let is_valid = uniform_case || checksummed[2..] == *digits;
+
+    let is_contract = match &payload.rpcUrl {
+        Some(url) => {
+            rpc::transport(url)?;
+            let code = rpc::call(&state.http, url, "eth_getCode", serde_json::json!([address, "latest"])).await?;
+            Some(code.as_str().and_then(abi::parse_hex).is_some_and(|code| !code.is_empty()))
+        }
+        None => None,
+    };
+    Ok(Json(serde_json::json!({
+        "checksummed": checksummed,
+        "isValid": is_valid,
+        "isContract": is_contract,
+    })))
+}
+
+async fn keccak(Json(payload): Json<KeccakRequest>) -> ApiResult<serde_json::Value> {
+    let bytes = match payload.encoding.as_deref().unwrap_or("utf8") {
+        "utf8" => payload.input.into_bytes(),
+        "hex" => abi::parse_hex(&payload.input)
+            .ok_or_else(|| ApiError::BadRequest("input is not valid hex".to_string()))?,
+        other => return Err(ApiError::BadRequest(format!("Unknown encoding {}; expected utf8 or hex", other))),
+    };
+    let hash = ethers::utils::keccak256(&bytes);
+    Ok(Json(serde_json::json!({ "hash": format!("0x{}", ethers::utils::hex::encode(hash)) })))
+}
+
+/// topic0 of an event, from its signature as written in Solidity.
+async fn event_topic(Json(payload): Json<EventTopicRequest>) -> ApiResult<serde_json::Value> {
+    let event = abi::parse_event(&payload.signature)
+        .ok_or_else(|| ApiError::BadRequest(format!("Invalid event signature {}", payload.signature)))?;
+    Ok(Json(serde_json::json!({
+        "signature": abi::event_signature(&event),
+        "topic": abi::event_topic(&event),
+        "anonymous": event.anonymous,
+    })))
+}
+
+/// Strong ETags for every embedded UI file, computed once on first request.
+fn ui_etag(path: &std::path::Path) -> Option<&'static String> {
+    static ETAGS: OnceLock<HashMap<PathBuf, String>> = OnceLock::new();
+    fn collect(dir: &'static Dir<'static>, etags: &mut HashMap<PathBuf, String>) {
+        for file in dir.files() {
+            let hash = ethers::utils::keccak256(file.contents());
+            etags.insert(file.path().to_path_buf(), format!("\"{}\"", ethers::utils::hex::encode(&hash[..16])));
+        }
+        for sub in dir.dirs() {
+            collect(sub, etags);
         }
     }
+    ETAGS.get_or_init(|| {
+        let mut etags = HashMap::new();
+        collect(&UI_DIR, &mut etags);
+        etags
+    }).get(path)
 }
 
-async fn serve_ui(Path(path): Path<String>) -> Response {
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers.get_all(header::IF_NONE_MATCH).iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == etag || tag == "*")
+}
+
+/// Reads `path` under `--ui-dir`. Only plain relative paths are looked
+/// up, so nothing outside the directory can be served.
+fn ui_disk_file(dir: &std::path::Path, path: &str) -> Option<(PathBuf, Vec<u8>)> {
+    let relative = std::path::Path::new(path);
+    if !relative.components().all(|c| matches!(c, std::path::Component::Normal(_))) {
+        return None;
+    }
+    let contents = std::fs::read(dir.join(relative)).ok()?;
+    Some((relative.to_path_buf(), contents))
+}
+
+async fn serve_ui(State(state): State<Arc<AppState>>, Path(path): Path<String>, req_headers: HeaderMap) -> Response {
     let trimmed = path.trim_start_matches('/');
     let file_path = if trimmed.is_empty() { "index.html" } else { trimmed };
-    let file = UI_DIR.get_file(file_path).or_else(|| UI_DIR.get_file("index.html"));
+    // Client-side routes get index.html; a missing file (anything with an
+    // extension, like a stale `assets/*.js`) is a real 404, not HTML with
+    // the wrong MIME type.
+    let spa_route = std::path::Path::new(file_path).extension().is_none();
+
+    // Files on disk may be rebuilt at any time: no long-lived caching, and
+    // ETags come from the current contents.
+    let (path, contents, etag, cache_control) = if let Some(dir) = &state.ui_dir {
+        let Some((path, contents)) = ui_disk_file(dir, file_path).or_else(|| spa_route.then(|| ui_disk_file(dir, "index.html")).flatten()) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let hash = ethers::utils::keccak256(&contents);
+        let etag = format!("\"{}\"", ethers::utils::hex::encode(&hash[..16]));
+        (path, axum::body::Body::from(contents), Some(etag), "no-cache")
+    } else {
+        let Some(file) = UI_DIR.get_file(file_path).or_else(|| spa_route.then(|| UI_DIR.get_file("index.html")).flatten()) else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        // Vite fingerprints everything under assets/, so those never change
+        // under the same name. Everything else must be revalidated.
+        let cache_control = if file.path().starts_with("assets") {
+            "public, max-age=31536000, immutable"
+        } else {
+            "no-cache"
+        };
+        (file.path().to_path_buf(), axum::body::Body::from(file.contents()), ui_etag(file.path()).cloned(), cache_control)
+    };
 
-    if let Some(file) = file {
-        let mime = mime_guess::from_path(file.path()).first_or_octet_stream();
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            header::CONTENT_TYPE,
-            HeaderValue::from_str(mime.as_ref()).unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
-        );
-        return (StatusCode::OK, headers, file.contents()).into_response();
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Some(etag) = etag {
+        if let Ok(value) = HeaderValue::from_str(&etag) {
+            headers.insert(header::ETAG, value);
+        }
+        if etag_matches(&req_headers, &etag) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
     }
 
-    StatusCode::NOT_FOUND.into_response()
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(mime.as_ref()).unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    (StatusCode::OK, headers, contents).into_response()
 }
 
 async fn start_fork(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<ForkStartRequest>,
-) -> Response {
+) -> ApiResult<serde_json::Value> {
+    rpc::transport(&payload.rpcUrl)?;
+    // Tags are pinned to the block they name now, on the upstream node
+    let block_number = match &payload.blockNumber {
+        Some(block) => Some(block.resolve(&state.http, &payload.rpcUrl).await?),
+        None => None,
+    };
     let mut node = state.fork_node.lock().unwrap();
     if node.is_running() {
-        node.stop();
+        // Waits for the old fork to shut down
+        tokio::task::block_in_place(|| node.stop());
     }
 
-    match node.start_fork(payload.rpcUrl.clone(), payload.blockNumber) {
-        Ok(_) => {
-            Json(serde_json::json!({
-                "status": "running",
-                "rpcUrl": payload.rpcUrl,
-                "blockNumber": payload.blockNumber,
-                "port": node.port(),
-            })).into_response()
+    node.start_fork(payload.rpcUrl.clone(), block_number)
+        .map_err(|e| match e.downcast::<std::io::Error>() {
+            Ok(io) => ApiError::spawn_failed("anvil", io),
+            Err(e) => ApiError::Internal(format!("Failed to start forked anvil: {}", e)),
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "status": "running",
+        "rpcUrl": payload.rpcUrl,
+        "blockNumber": block_number,
+        "port": node.port(),
+    })))
+}
+
+async fn stop_fork(State(state): State<Arc<AppState>>) -> Json<serde_json::Value> {
+    let mut node = state.fork_node.lock().unwrap();
+    tokio::task::block_in_place(|| node.stop());
+    Json(serde_json::json!({ "status": "stopped" }))
+}
+
+/// Resets the running fork in place with `anvil_reset`, optionally to a new
+/// block. Nodes without `anvil_reset` are restarted instead.
+async fn reset_fork(
+    State(state): State<Arc<AppState>>,
+    payload: Option<Json<ForkResetRequest>>,
+) -> ApiResult<serde_json::Value> {
+    let block = payload.and_then(|Json(p)| p.blockNumber);
+    let (fork_url, port) = {
+        let node = state.fork_node.lock().unwrap();
+        match (node.is_running(), node.fork_info().0) {
+            (true, Some(url)) => (url, node.port()),
+            _ => return Err(ApiError::BadRequest("No fork is running".to_string())),
         }
-        Err(e) => {
-            Json(serde_json::json!({
-                "error": format!("Failed to start forked anvil: {}", e)
-            })).into_response()
+    };
+    let block_number = match &block {
+        Some(block) => Some(block.resolve(&state.http, &fork_url).await?),
+        None => None,
+    };
+    let node_url = state.fork_node.lock().unwrap().url();
+
+    let mut forking = serde_json::json!({ "jsonRpcUrl": fork_url });
+    if let Some(block) = block_number {
+        forking["blockNumber"] = block.into();
+    }
+    match rpc::call(&state.http, &node_url, "anvil_reset", serde_json::json!([{ "forking": forking }])).await {
+        Ok(_) => {}
+        Err(e) if e.is_method_not_found() => {
+            tracing::info!("Fork on port {} doesn't support anvil_reset, restarting it", port);
+            let mut node = state.fork_node.lock().unwrap();
+            tokio::task::block_in_place(|| node.stop());
+            node.start_fork(fork_url.clone(), block_number)
+                .map_err(|e| match e.downcast::<std::io::Error>() {
+                    Ok(io) => ApiError::spawn_failed("anvil", io),
+                    Err(e) => ApiError::Internal(format!("Failed to restart forked anvil: {}", e)),
+                })?;
+            return Ok(Json(serde_json::json!({
+                "status": "restarted",
+                "rpcUrl": fork_url,
+                "blockNumber": block_number,
+                "port": port,
+            })));
         }
+        Err(e) => return Err(e.into()),
     }
-}
 
-async fn stop_fork(State(state): State<Arc<AppState>>) -> Response {
-    let mut node = state.fork_node.lock().unwrap();
-    node.stop();
-    Json(serde_json::json!({ "status": "stopped" })).into_response()
+    // Without a block the fork follows the upstream head, so ask the node
+    let block = match block_number {
+        Some(block) => Some(block),
+        None => rpc::call(&state.http, &node_url, "eth_blockNumber", serde_json::json!([])).await
+            .ok()
+            .and_then(|b| rpc::quantity(&b))
+            .map(|b| b.low_u64()),
+    };
+    state.fork_node.lock().unwrap().set_fork_block(block_number);
+
+    Ok(Json(serde_json::json!({
+        "status": "reset",
+        "rpcUrl": fork_url,
+        "blockNumber": block,
+        "port": port,
+    })))
 }
 
-async fn fork_status(State(state): State<Arc<AppState>>) -> Response {
-    let node = state.fork_node.lock().unwrap();
-    let (rpc_url, block_number) = node.fork_info();
-    let payload = ForkStatusResponse {
-        running: node.is_running(),
-        rpcUrl: rpc_url,
-        blockNumber: block_number,
-        port: node.port(),
+async fn fork_status(State(state): State<Arc<AppState>>) -> Json<ForkStatusResponse> {
+    let mut payload = {
+        let node = state.fork_node.lock().unwrap();
+        let (rpc_url, fork_block) = node.fork_info();
+        ForkStatusResponse {
+            running: node.is_running(),
+            rpcUrl: rpc_url,
+            forkBlock: fork_block,
+            currentBlock: None,
+            port: node.port(),
+            exitStatus: node.exit_status(),
+            logging: node.logging(),
+        }
     };
-    Json(payload).into_response()
+    if payload.running {
+        let url = state.fork_node.lock().unwrap().url();
+        // Left null if the node doesn't answer; the rest is still useful
+        payload.currentBlock = rpc::call(&state.http, &url, "eth_blockNumber", serde_json::json!([])).await
+            .ok()
+            .and_then(|n| rpc::quantity(&n))
+            .and_then(|n| u64::try_from(n).ok());
+    }
+    Json(payload)
 }
 
-async fn list_keystores() -> Response {
+async fn list_keystores() -> Json<KeystoreListResponse> {
     let mut accounts = Vec::new();
     // foundry keystores are in ~/.foundry/keystores
     if let Ok(home) = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME")) {
@@ -556,50 +2771,45 @@ async fn list_keystores() -> Response {
             }
         }
     }
-    Json(KeystoreListResponse { accounts }).into_response()
+    Json(KeystoreListResponse { accounts })
 }
 
 async fn unlock_keystore(
+    State(state): State<Arc<AppState>>,
     Json(payload): Json<KeystoreUnlockRequest>,
-) -> Response {
+) -> ApiResult<KeystoreUnlockResponse> {
     let home = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME")).unwrap_or(".".to_string());
     let keystore_path = PathBuf::from(home).join(".foundry").join("keystores").join(&payload.account);
+    if !keystore_path.is_file() {
+        return Err(ApiError::NotFound(format!("Keystore {} not found", payload.account)));
+    }
 
     // cast wallet decrypt-keystore <PATH> --unsafe-password <PASS>
-    let output = Command::new("cast")
+    let output = run_timed(&state, Command::new("cast")
         .arg("wallet")
         .arg("decrypt-keystore")
         .arg(keystore_path)
         .arg("--unsafe-password")
-        .arg(&payload.password)
-        .output();
-
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                // Output format: "... private key is: 0x..."
-                // We take the last word.
-                let private_key = stdout.trim().split_whitespace().last().unwrap_or("").to_string();
-                
-                if private_key.starts_with("0x") {
-                     Json(KeystoreUnlockResponse { privateKey: private_key }).into_response()
-                } else {
-                     // Fallback: try to find it in the string if formatting is different
-                     if let Some(start) = stdout.find("0x") {
-                         let pk = &stdout[start..];
-                         let pk = pk.split_whitespace().next().unwrap_or("").to_string();
-                         Json(KeystoreUnlockResponse { privateKey: pk }).into_response()
-                     } else {
-                         Json(serde_json::json!({"error": format!("Could not parse private key from output: {}", stdout)})).into_response()
-                     }
-                }
-            } else {
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                Json(serde_json::json!({"error": format!("Decryption failed: {}", stderr)})).into_response()
-            }
-        },
-        Err(e) => Json(serde_json::json!({"error": format!("Failed to execute cast: {}", e)})).into_response()
+        .arg(&payload.password))?;
+
+    if !output.status.success() {
+        return Err(ApiError::BadRequest(error::command_failed("Decryption failed", &output)));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Output format: "... private key is: 0x..."
+    // We take the last word.
+    let private_key = stdout.split_whitespace().last().unwrap_or("").to_string();
+
+    if private_key.starts_with("0x") {
+        Ok(Json(KeystoreUnlockResponse { privateKey: private_key }))
+    } else if let Some(start) = stdout.find("0x") {
+        // Fallback: try to find it in the string if formatting is different
+        let pk = &stdout[start..];
+        let pk = pk.split_whitespace().next().unwrap_or("").to_string();
+        Ok(Json(KeystoreUnlockResponse { privateKey: pk }))
+    } else {
+        Err(ApiError::Upstream(format!("Could not parse private key from output: {}", stdout)))
     }
 }
 
@@ -610,13 +2820,14 @@ struct KeystoreRemoveRequest {
 }
 
 async fn remove_keystore(
+    State(state): State<Arc<AppState>>,
     Json(payload): Json<KeystoreRemoveRequest>,
-) -> Response {
+) -> ApiResult<serde_json::Value> {
     let home = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME")).unwrap_or(".".to_string());
     let keystore_root = PathBuf::from(home).join(".foundry").join("keystores");
 
     // cast wallet remove --name <NAME> --dir <DIR> --unsafe-password <PASS>
-    let output = Command::new("cast")
+    let output = run_timed(&state, Command::new("cast")
         .arg("wallet")
         .arg("remove")
         .arg("--name")
@@ -624,25 +2835,18 @@ async fn remove_keystore(
         .arg("--dir")
         .arg(keystore_root)
         .arg("--unsafe-password")
-        .arg(&payload.password)
-        .output();
+        .arg(&payload.password))?;
 
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                Json(serde_json::json!({"status": "success"})).into_response()
-            } else {
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                Json(serde_json::json!({"error": format!("Remove failed: {}", stderr)})).into_response()
-            }
-        },
-        Err(e) => Json(serde_json::json!({"error": format!("Failed to execute cast: {}", e)})).into_response()
+    if !output.status.success() {
+        return Err(ApiError::BadRequest(error::command_failed("Remove failed", &output)));
     }
+    Ok(Json(serde_json::json!({"status": "success"})))
 }
 
 async fn create_keystore(
+    State(state): State<Arc<AppState>>,
     Json(payload): Json<KeystoreCreateRequest>,
-) -> Response {
+) -> ApiResult<serde_json::Value> {
     let home = std::env::var("USERPROFILE").or_else(|_| std::env::var("HOME")).unwrap_or(".".to_string());
     let keystore_root = PathBuf::from(home).join(".foundry").join("keystores");
     
@@ -675,17 +2879,10 @@ async fn create_keystore(
     }
 
     // No stdin needed anymore
-    let output = cmd.output();
+    let output = run_timed(&state, &mut cmd)?;
 
-    match output {
-        Ok(out) => {
-            if out.status.success() {
-                Json(serde_json::json!({"status": "success", "account": payload.account})).into_response()
-            } else {
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                Json(serde_json::json!({"error": format!("Operation failed: {}", stderr)})).into_response()
-            }
-        },
-        Err(e) => Json(serde_json::json!({"error": format!("Failed to execute cast: {}", e)})).into_response()
+    if !output.status.success() {
+        return Err(ApiError::BadRequest(error::command_failed("Operation failed", &output)));
     }
+    Ok(Json(serde_json::json!({"status": "success", "account": payload.account})))
 }
\ No newline at end of file
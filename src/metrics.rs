@@ -0,0 +1,77 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Process-wide counters served by `/metrics`.
+#[derive(Default)]
+pub struct Metrics {
+    compiles: AtomicU64,
+    compile_failures: AtomicU64,
+    compile_micros: AtomicU64,
+    ws_connections: AtomicU64,
+    subprocesses_rejected: AtomicU64,
+    /// Keyed by program name (`forge`, `cast`, ...).
+    subprocesses: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn record_compile(&self, elapsed: Duration, success: bool) {
+        self.compiles.fetch_add(1, Ordering::Relaxed);
+        self.compile_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if !success {
+            self.compile_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_subprocess(&self, tool: &str) {
+        *self.subprocesses.lock().unwrap().entry(tool.to_string()).or_default() += 1;
+    }
+
+    /// A command refused because `--max-concurrent-commands` were running.
+    pub fn record_rejected_subprocess(&self) {
+        self.subprocesses_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a WebSocket client for as long as the returned guard lives.
+    pub fn ws_connection(&self) -> ConnectionGuard<'_> {
+        self.ws_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(&self.ws_connections)
+    }
+
+    /// Prometheus text exposition format. The compile duration is a summary,
+    /// so the average is `chasm_compile_duration_seconds_sum / _count`.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{}", name, help, name, kind, value);
+        };
+        let compiles = self.compiles.load(Ordering::Relaxed);
+        metric("chasm_compiles_total", "counter", "Compiles run, including failures.",
+            format!("chasm_compiles_total {}", compiles));
+        metric("chasm_compile_failures_total", "counter", "Compiles that failed.",
+            format!("chasm_compile_failures_total {}", self.compile_failures.load(Ordering::Relaxed)));
+        let seconds = self.compile_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        metric("chasm_compile_duration_seconds", "summary", "Wall-clock time spent compiling.",
+            format!("chasm_compile_duration_seconds_sum {}\nchasm_compile_duration_seconds_count {}", seconds, compiles));
+        metric("chasm_websocket_connections", "gauge", "WebSocket clients currently connected.",
+            format!("chasm_websocket_connections {}", self.ws_connections.load(Ordering::Relaxed)));
+        let tools = self.subprocesses.lock().unwrap().iter()
+            .map(|(tool, count)| format!("chasm_subprocess_invocations_total{{tool=\"{}\"}} {}", tool, count))
+            .collect::<Vec<_>>()
+            .join("\n");
+        metric("chasm_subprocess_invocations_total", "counter", "External tools run, by program.", tools);
+        metric("chasm_subprocess_rejected_total", "counter", "External tool runs refused at the concurrency limit.",
+            format!("chasm_subprocess_rejected_total {}", self.subprocesses_rejected.load(Ordering::Relaxed)));
+        out
+    }
+}
+
+pub struct ConnectionGuard<'a>(&'a AtomicU64);
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
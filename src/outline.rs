@@ -0,0 +1,73 @@
+use foundry_compilers::artifacts::ast::{Ast, Node, NodeType};
+use serde::Serialize;
+
+/// A declaration in a source file, with its byte range and 1-based line.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Symbol {
+    pub kind: &'static str,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_mutability: Option<String>,
+    pub start: usize,
+    pub length: Option<usize>,
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Symbol>,
+}
+
+/// Top-level symbols (contracts with their members, plus free functions and
+/// types) of the source unit `ast`. `source` is the file's text, used to
+/// turn byte offsets into line numbers.
+pub fn symbols(ast: &Ast, source: Option<&str>) -> Vec<Symbol> {
+    ast.nodes.iter().filter_map(|node| symbol(node, source)).collect()
+}
+
+fn symbol(node: &Node, source: Option<&str>) -> Option<Symbol> {
+    let kind = match node.node_type {
+        NodeType::ContractDefinition => node.attribute::<String>("contractKind")
+            .map_or("contract", |kind| match kind.as_str() {
+                "interface" => "interface",
+                "library" => "library",
+                _ => "contract",
+            }),
+        NodeType::FunctionDefinition => node.attribute::<String>("kind")
+            .map_or("function", |kind| match kind.as_str() {
+                "constructor" => "constructor",
+                "fallback" => "fallback",
+                "receive" => "receive",
+                _ => "function",
+            }),
+        NodeType::VariableDeclaration if node.attribute::<bool>("stateVariable") == Some(true) => "variable",
+        NodeType::EventDefinition => "event",
+        NodeType::ErrorDefinition => "error",
+        NodeType::ModifierDefinition => "modifier",
+        NodeType::StructDefinition => "struct",
+        NodeType::EnumDefinition => "enum",
+        NodeType::UserDefinedValueTypeDefinition => "type",
+        _ => return None,
+    };
+
+    let children = if node.node_type == NodeType::ContractDefinition {
+        node.nodes.iter().filter_map(|child| symbol(child, source)).collect()
+    } else {
+        Vec::new()
+    };
+    Some(Symbol {
+        kind,
+        name: node.attribute("name").unwrap_or_default(),
+        visibility: node.attribute("visibility"),
+        state_mutability: node.attribute("stateMutability"),
+        start: node.src.start,
+        length: node.src.length,
+        line: source.and_then(|s| line_of(s, node.src.start)),
+        children,
+    })
+}
+
+fn line_of(source: &str, offset: usize) -> Option<usize> {
+    let before = source.as_bytes().get(..offset)?;
+    Some(before.iter().filter(|&&b| b == b'\n').count() + 1)
+}
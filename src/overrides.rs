@@ -0,0 +1,136 @@
+use ethers::types::U256;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use crate::abi;
+use crate::error::ApiError;
+use crate::rpc;
+
+/// Hypothetical state for one account, as accepted by geth's
+/// `debug_traceCall` `stateOverrides`.
+///
+/// Backends differ in what they honour:
+/// - `debug_traceCall` (geth, anvil, reth): every field, scoped to the call.
+/// - `cast call --trace` (`/trace/calltree`): applied with `anvil_setBalance`,
+///   `anvil_setNonce`, `anvil_setCode` and `anvil_setStorageAt` inside an
+///   `evm_snapshot`/`evm_revert` pair, so it only works against anvil and
+///   only affects the latest block.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AccountOverride {
+    balance: Option<serde_json::Value>,
+    nonce: Option<serde_json::Value>,
+    code: Option<String>,
+    state_diff: Option<BTreeMap<String, String>>,
+}
+
+pub type StateOverrides = BTreeMap<String, AccountOverride>;
+
+/// An override with every value checked and in JSON-RPC form: quantities
+/// as `0x` hex, code as `0x` bytes, slots and values as 32-byte words.
+pub struct Normalized {
+    address: String,
+    balance: Option<String>,
+    nonce: Option<String>,
+    code: Option<String>,
+    state_diff: Vec<(String, String)>,
+}
+
+/// Accepts numbers, decimal strings and `0x` hex strings.
+fn quantity(value: &serde_json::Value) -> Option<String> {
+    let parsed = match value {
+        serde_json::Value::Number(n) => U256::from(n.as_u64()?),
+        serde_json::Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok()?,
+            None => U256::from_dec_str(s).ok()?,
+        },
+        _ => return None,
+    };
+    Some(format!("{:#x}", parsed))
+}
+
+fn word(value: &str) -> Option<String> {
+    let bytes = abi::parse_hex(value).filter(|b| b.len() <= 32)?;
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    Some(format!("0x{}", ethers::utils::hex::encode(padded)))
+}
+
+pub fn validate(overrides: &StateOverrides) -> Result<Vec<Normalized>, ApiError> {
+    overrides.iter().map(|(address, account)| {
+        let invalid = |field: &str| ApiError::BadRequest(format!("Invalid {} override for {}", field, address));
+        if abi::parse_hex(address).filter(|b| b.len() == 20).is_none() {
+            return Err(ApiError::BadRequest(format!("Invalid override address {}", address)));
+        }
+        let state_diff = account.state_diff.iter().flatten()
+            .map(|(slot, value)| Some((word(slot)?, word(value)?)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("stateDiff"))?;
+        Ok(Normalized {
+            address: address.clone(),
+            balance: account.balance.as_ref().map(|b| quantity(b).ok_or_else(|| invalid("balance"))).transpose()?,
+            nonce: account.nonce.as_ref().map(|n| quantity(n).ok_or_else(|| invalid("nonce"))).transpose()?,
+            code: account.code.as_ref()
+                .map(|c| abi::parse_hex(c).map(|b| format!("0x{}", ethers::utils::hex::encode(b))).ok_or_else(|| invalid("code")))
+                .transpose()?,
+            state_diff,
+        })
+    }).collect()
+}
+
+/// The `stateOverrides` object for `debug_traceCall`'s tracer config.
+pub fn to_trace_config(overrides: &[Normalized]) -> serde_json::Value {
+    let accounts: serde_json::Map<String, serde_json::Value> = overrides.iter().map(|o| {
+        let mut account = serde_json::Map::new();
+        if let Some(balance) = &o.balance {
+            account.insert("balance".to_string(), balance.clone().into());
+        }
+        if let Some(nonce) = &o.nonce {
+            account.insert("nonce".to_string(), nonce.clone().into());
+        }
+        if let Some(code) = &o.code {
+            account.insert("code".to_string(), code.clone().into());
+        }
+        if !o.state_diff.is_empty() {
+            let diff: serde_json::Map<_, _> = o.state_diff.iter()
+                .map(|(slot, value)| (slot.clone(), value.clone().into()))
+                .collect();
+            account.insert("stateDiff".to_string(), diff.into());
+        }
+        (o.address.clone(), account.into())
+    }).collect();
+    serde_json::json!({ "stateOverrides": accounts })
+}
+
+/// Snapshots the anvil node at `url` and applies `overrides` to it. Pass the
+/// returned snapshot id to `revert` once done.
+pub async fn apply_on_anvil(client: &reqwest::Client, url: &str, overrides: &[Normalized]) -> Result<serde_json::Value, ApiError> {
+    let snapshot = rpc::call(client, url, "evm_snapshot", serde_json::json!([])).await?;
+    let mut calls = Vec::new();
+    for o in overrides {
+        if let Some(balance) = &o.balance {
+            calls.push(("anvil_setBalance", serde_json::json!([o.address, balance])));
+        }
+        if let Some(nonce) = &o.nonce {
+            calls.push(("anvil_setNonce", serde_json::json!([o.address, nonce])));
+        }
+        if let Some(code) = &o.code {
+            calls.push(("anvil_setCode", serde_json::json!([o.address, code])));
+        }
+        for (slot, value) in &o.state_diff {
+            calls.push(("anvil_setStorageAt", serde_json::json!([o.address, slot, value])));
+        }
+    }
+    for (method, params) in calls {
+        if let Err(e) = rpc::call(client, url, method, params).await {
+            revert(client, url, snapshot).await;
+            return Err(e.into());
+        }
+    }
+    Ok(snapshot)
+}
+
+pub async fn revert(client: &reqwest::Client, url: &str, snapshot: serde_json::Value) {
+    if let Err(e) = rpc::call(client, url, "evm_revert", serde_json::json!([snapshot])).await {
+        tracing::warn!("Failed to revert state overrides on {}: {}", url, e);
+    }
+}
@@ -0,0 +1,276 @@
+use foundry_compilers::artifacts::{remappings::Remapping, Source};
+use foundry_compilers::solc::Solc;
+use semver::VersionReq;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use walkdir::{DirEntry, WalkDir};
+
+/// Directories that never contain the user's own sources: build output,
+/// caches, VCS metadata and vendored dependencies.
+pub const IGNORED_DIRS: [&str; 6] = [".git", "node_modules", "lib", "out", "cache", "target"];
+
+const HARDHAT_CONFIGS: [&str; 4] = ["hardhat.config.js", "hardhat.config.ts", "hardhat.config.cjs", "hardhat.config.mjs"];
+
+/// Pragmas are expected near the top of a file; don't read whole sources.
+const PRAGMA_SCAN_LINES: usize = 20;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectInfo {
+    pub root: PathBuf,
+    pub src: PathBuf,
+    pub has_foundry_toml: bool,
+    pub has_remappings: bool,
+    pub hardhat: bool,
+    pub libs: Vec<PathBuf>,
+    pub solc_version: Option<String>,
+    pub files: Vec<PathBuf>,
+}
+
+impl ProjectInfo {
+    pub fn detect(root: &Path) -> Self {
+        let src = source_dir(root);
+        let files = sol_files(&src);
+        Self {
+            root: root.to_path_buf(),
+            has_foundry_toml: root.join("foundry.toml").is_file(),
+            has_remappings: root.join("remappings.txt").is_file(),
+            hardhat: is_hardhat(root),
+            libs: lib_dirs(root),
+            solc_version: detect_solc_version(&files),
+            files: files.iter().map(|f| f.strip_prefix(root).unwrap_or(f).to_path_buf()).collect(),
+            src,
+        }
+    }
+}
+
+/// Resolves where sources live: `contracts/` if present, otherwise the root.
+/// Everything that needs the source dir must go through here.
+pub fn source_dir(root: &Path) -> PathBuf {
+    let contracts_dir = root.join("contracts");
+    if contracts_dir.is_dir() {
+        contracts_dir
+    } else {
+        root.to_path_buf()
+    }
+}
+
+pub fn is_hardhat(root: &Path) -> bool {
+    HARDHAT_CONFIGS.iter().any(|name| root.join(name).is_file())
+}
+
+/// Dependency directories to resolve imports from. Unlike foundry-compilers'
+/// own detection, both `lib/` and `node_modules/` are used when both exist so
+/// mixed Foundry/Hardhat projects resolve `@scope/...` npm imports too.
+pub fn lib_dirs(root: &Path) -> Vec<PathBuf> {
+    ["lib", "node_modules"].iter()
+        .map(|name| root.join(name))
+        .filter(|dir| dir.is_dir())
+        .collect()
+}
+
+/// The entries of `root`'s `remappings.txt`, one `prefix=path` per line,
+/// with relative paths made absolute against `root` as Foundry does. Lines
+/// that don't parse are skipped with a warning; a missing file has none.
+pub fn file_remappings(root: &Path) -> Vec<Remapping> {
+    let Ok(text) = std::fs::read_to_string(root.join("remappings.txt")) else { return Vec::new() };
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| match line.parse::<Remapping>() {
+            Ok(mut remapping) => {
+                if Path::new(&remapping.path).is_relative() {
+                    let trailing = if remapping.path.ends_with('/') { "/" } else { "" };
+                    let path = root.join(&remapping.path).to_string_lossy().trim_end_matches('/').to_string();
+                    remapping.path = format!("{}{}", path, trailing);
+                }
+                Some(remapping)
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring remapping {:?} in remappings.txt: {}", line, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// `via_ir` in `foundry.toml`'s default profile, if it's set there.
+pub fn foundry_via_ir(root: &Path) -> Option<bool> {
+    let text = std::fs::read_to_string(root.join("foundry.toml")).ok()?;
+    let config: toml::Table = text.parse().ok()?;
+    config.get("profile")?.get("default")?.get("via_ir")?.as_bool()
+}
+
+/// Sets the variables in `root`'s `.env` that aren't set already, as Foundry
+/// does, so `forge` and `cast` see them too. Returns their names; a missing
+/// file sets none.
+pub fn load_dotenv(root: &Path) -> Result<Vec<String>, dotenvy::Error> {
+    let iter = match dotenvy::from_path_iter(root.join(".env")) {
+        Ok(iter) => iter,
+        Err(e) if e.not_found() => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut loaded = Vec::new();
+    for item in iter {
+        let (key, value) = item?;
+        if std::env::var_os(&key).is_none() {
+            std::env::set_var(&key, value);
+            loaded.push(key);
+        }
+    }
+    Ok(loaded)
+}
+
+pub fn node_modules_dir(root: &Path) -> Option<PathBuf> {
+    let dir = root.join("node_modules");
+    dir.is_dir().then_some(dir)
+}
+
+pub fn is_ignored(entry: &DirEntry) -> bool {
+    entry.file_type().is_dir()
+        && entry.file_name().to_str().is_some_and(|name| IGNORED_DIRS.contains(&name))
+}
+
+/// All `.sol` files under `dir`, skipping ignored directories.
+pub fn sol_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !is_ignored(e))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "sol"))
+        .map(|e| e.into_path())
+        .collect();
+    files.sort();
+    files
+}
+
+/// A node in the source tree; directories have `children`, files don't.
+#[derive(Serialize)]
+pub struct FileNode {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<FileNode>>,
+}
+
+/// Builds the `.sol` tree under the source dir, with paths relative to `root`.
+pub fn file_tree(root: &Path) -> Vec<FileNode> {
+    let mut tree = Vec::new();
+    for file in sol_files(&source_dir(root)) {
+        let rel = file.strip_prefix(root).unwrap_or(&file);
+        insert_node(&mut tree, PathBuf::new(), &rel.components().collect::<Vec<_>>());
+    }
+    tree
+}
+
+fn insert_node(nodes: &mut Vec<FileNode>, parent: PathBuf, parts: &[Component]) {
+    let Some((first, rest)) = parts.split_first() else { return };
+    let name = first.as_os_str().to_string_lossy().to_string();
+    let path = parent.join(&name);
+    if rest.is_empty() {
+        nodes.push(FileNode { name, path, children: None });
+        return;
+    }
+    let idx = match nodes.iter().position(|n| n.name == name && n.children.is_some()) {
+        Some(idx) => idx,
+        None => {
+            nodes.push(FileNode { name, path: path.clone(), children: Some(Vec::new()) });
+            nodes.len() - 1
+        }
+    };
+    if let Some(children) = nodes[idx].children.as_mut() {
+        insert_node(children, path, rest);
+    }
+}
+
+/// Resolves a client-supplied relative path, returning `None` if it would
+/// land outside `root` (via `..`, an absolute path, or a symlink). The target
+/// itself doesn't need to exist yet.
+pub fn resolve_in_root(root: &Path, rel: &str) -> Option<PathBuf> {
+    let rel = Path::new(rel);
+    if rel.components().any(|c| !matches!(c, Component::Normal(_) | Component::CurDir)) {
+        return None;
+    }
+    let root = dunce::canonicalize(root).ok()?;
+    let joined = root.join(rel);
+
+    // Canonicalize the nearest existing ancestor so symlinks can't escape
+    let mut existing = joined.as_path();
+    let mut missing = Vec::new();
+    while !existing.exists() {
+        missing.push(existing.file_name()?);
+        existing = existing.parent()?;
+    }
+    let mut resolved = dunce::canonicalize(existing).ok()?;
+    for part in missing.iter().rev() {
+        resolved.push(part);
+    }
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+/// Finds the file declaring `contract` by the `<Name>.sol` convention.
+pub fn find_contract_file(root: &Path, contract: &str) -> Option<PathBuf> {
+    contract_file_in(sol_files(root), contract)
+}
+
+/// `find_contract_file` among `files`.
+pub fn contract_file_in(files: Vec<PathBuf>, contract: &str) -> Option<PathBuf> {
+    let file_name = format!("{}.sol", contract);
+    files.into_iter()
+        .find(|p| p.file_name().is_some_and(|name| name.to_string_lossy() == file_name))
+}
+
+/// Picks the newest known solc release that satisfies every file's pragma.
+/// Returns `None` when there are no pragmas or no single version fits them all.
+/// Runs before every compile, so pragmas are cached per file; see `pragma_req`.
+pub fn detect_solc_version(files: &[PathBuf]) -> Option<String> {
+    let reqs: Vec<_> = files.iter()
+        .filter_map(|path| pragma_req(path))
+        .collect();
+    if reqs.is_empty() {
+        return None;
+    }
+
+    let mut versions = Solc::released_versions();
+    versions.extend(Solc::installed_versions());
+    versions.sort();
+    versions.iter().rev()
+        .find(|v| reqs.iter().all(|req| req.matches(v)))
+        .map(|v| v.to_string())
+}
+
+/// The file's version pragma, re-read only when its mtime changes.
+fn pragma_req(path: &Path) -> Option<VersionReq> {
+    type PragmaCache = HashMap<PathBuf, (SystemTime, Option<VersionReq>)>;
+    static PRAGMAS: OnceLock<Mutex<PragmaCache>> = OnceLock::new();
+    let pragmas = PRAGMAS.get_or_init(Default::default);
+
+    let mtime = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    if let Some(mtime) = mtime {
+        if let Some((seen, req)) = pragmas.lock().unwrap().get(path) {
+            if *seen == mtime {
+                return req.clone();
+            }
+        }
+    }
+
+    let req = read_pragma_header(path)
+        .and_then(|header| Solc::source_version_req(&Source::new(header)).ok());
+    if let Some(mtime) = mtime {
+        pragmas.lock().unwrap().insert(path.to_path_buf(), (mtime, req.clone()));
+    }
+    req
+}
+
+fn read_pragma_header(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let lines: Vec<String> = BufReader::new(file).lines()
+        .take(PRAGMA_SCAN_LINES)
+        .map_while(Result::ok)
+        .collect();
+    Some(lines.join("\n"))
+}
@@ -0,0 +1,211 @@
+use ethers::providers::{JsonRpcClient, Ws, WsClientError};
+use ethers::types::U256;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use crate::abi;
+use crate::error::ApiError;
+
+/// JSON-RPC "method not found", returned by nodes lacking a namespace.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// The request never produced a JSON-RPC response.
+    Transport(String),
+    /// The node answered with a JSON-RPC error object.
+    Rpc { code: i64, message: String, data: Option<serde_json::Value> },
+}
+
+impl RpcError {
+    pub fn is_method_not_found(&self) -> bool {
+        matches!(self, RpcError::Rpc { code, .. } if *code == METHOD_NOT_FOUND)
+    }
+
+    /// The revert data of a call that reverted: the error's `data`, or
+    /// what the message quotes for nodes that only put it there.
+    pub fn revert_data(&self) -> Option<Vec<u8>> {
+        let RpcError::Rpc { message, data, .. } = self else { return None };
+        match data {
+            Some(serde_json::Value::String(hex)) => abi::parse_hex(hex),
+            Some(data) => data.get("data").and_then(|d| d.as_str()).and_then(abi::parse_hex),
+            None => abi::revert_data_in(message),
+        }
+    }
+
+    /// Like the `ApiError` conversion, with custom errors in reverts
+    /// decoded against `abis` too.
+    pub fn into_api_error(self, abis: &[(String, ethers::abi::Abi)]) -> ApiError {
+        match self.revert_data() {
+            Some(data) => ApiError::Reverted { message: self.to_string(), revert: abi::decode_revert(abis, &data) },
+            None => ApiError::Upstream(self.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Transport(msg) => write!(f, "{}", msg),
+            RpcError::Rpc { code, message, .. } => write!(f, "RPC error {}: {}", code, message),
+        }
+    }
+}
+
+impl From<RpcError> for ApiError {
+    fn from(e: RpcError) -> Self {
+        e.into_api_error(&[])
+    }
+}
+
+/// How a JSON-RPC URL is reached, picked from its scheme.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Transport {
+    Http,
+    Ws,
+}
+
+/// Checks that `url` is an `http(s)://` or `ws(s)://` RPC URL.
+pub fn transport(url: &str) -> Result<Transport, ApiError> {
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    match scheme.as_deref() {
+        Some("http" | "https") => Ok(Transport::Http),
+        Some("ws" | "wss") => Ok(Transport::Ws),
+        _ => Err(ApiError::BadRequest(format!("Unsupported RPC URL {}: expected http(s):// or ws(s)://", url))),
+    }
+}
+
+/// Open websocket connections, reused across requests to the same URL.
+static WS_CLIENTS: OnceLock<Mutex<HashMap<String, Ws>>> = OnceLock::new();
+
+async fn ws_client(url: &str) -> Result<Ws, RpcError> {
+    let clients = WS_CLIENTS.get_or_init(Default::default);
+    if let Some(ws) = clients.lock().unwrap().get(url) {
+        return Ok(ws.clone());
+    }
+    let ws = Ws::connect(url).await
+        .map_err(|e| RpcError::Transport(format!("Failed to connect to {}: {}", url, e)))?;
+    clients.lock().unwrap().insert(url.to_string(), ws.clone());
+    Ok(ws)
+}
+
+async fn call_ws(url: &str, method: &str, params: serde_json::Value) -> Result<serde_json::Value, RpcError> {
+    let ws = ws_client(url).await?;
+    match JsonRpcClient::request(&ws, method, params).await {
+        Ok(result) => Ok(result),
+        Err(WsClientError::JsonRpcError(e)) => Err(RpcError::Rpc { code: e.code, message: e.message, data: e.data }),
+        Err(e) => {
+            // The connection may have dropped; reconnect on the next call
+            WS_CLIENTS.get_or_init(Default::default).lock().unwrap().remove(url);
+            Err(RpcError::Transport(format!("{} request to {} failed: {}", method, url, e)))
+        }
+    }
+}
+
+/// Sends a single JSON-RPC request and returns its `result`. `ws(s)://`
+/// URLs go over a websocket, anything else over HTTP with `client`.
+pub async fn call(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, RpcError> {
+    match transport(url) {
+        Ok(Transport::Ws) => return call_ws(url, method, params).await,
+        Ok(Transport::Http) => {}
+        Err(_) => return Err(RpcError::Transport(format!("Unsupported RPC URL {}: expected http(s):// or ws(s)://", url))),
+    }
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let res = client.post(url).json(&body).send().await
+        .map_err(|e| RpcError::Transport(format!("{} request to {} failed: {}", method, url, e)))?;
+    let mut json: serde_json::Value = res.json().await
+        .map_err(|e| RpcError::Transport(format!("Invalid {} response from {}: {}", method, url, e)))?;
+
+    if let Some(err) = json.get("error") {
+        return Err(RpcError::Rpc {
+            code: err.get("code").and_then(|c| c.as_i64()).unwrap_or_default(),
+            message: err.get("message").and_then(|m| m.as_str()).unwrap_or("unknown error").to_string(),
+            data: err.get("data").cloned(),
+        });
+    }
+    Ok(json.get_mut("result").map(serde_json::Value::take).unwrap_or(serde_json::Value::Null))
+}
+
+/// `call`, answered as a whole JSON-RPC response: node errors become its
+/// `error` object, and only transport failures are an `Err`.
+pub async fn call_response(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+    id: u64,
+) -> Result<serde_json::Value, RpcError> {
+    let mut response = serde_json::json!({ "jsonrpc": "2.0", "id": id });
+    match call(client, url, method, params).await {
+        Ok(result) => response["result"] = result,
+        Err(RpcError::Rpc { code, message, data }) => {
+            response["error"] = serde_json::json!({ "code": code, "message": message, "data": data });
+        }
+        Err(e) => return Err(e),
+    }
+    Ok(response)
+}
+
+/// Parses a hex `QUANTITY` such as `"0x1a"`.
+pub fn quantity(value: &serde_json::Value) -> Option<U256> {
+    let s = value.as_str()?;
+    U256::from_str_radix(s.trim_start_matches("0x"), 16).ok()
+}
+
+/// A block given as a number (decimal, or a `0x` hex string) or one of the
+/// tags `latest`, `earliest`, `safe` and `finalized`.
+#[derive(Clone, Debug)]
+pub enum BlockRef {
+    Number(u64),
+    Tag(&'static str),
+}
+
+const BLOCK_TAGS: [&str; 4] = ["latest", "earliest", "safe", "finalized"];
+
+impl<'de> serde::Deserialize<'de> for BlockRef {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Number(n) => return Ok(BlockRef::Number(n)),
+            Raw::Text(text) => text,
+        };
+        if let Some(tag) = BLOCK_TAGS.iter().find(|tag| **tag == text) {
+            return Ok(BlockRef::Tag(tag));
+        }
+        match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        }
+        .map(BlockRef::Number)
+        .ok_or_else(|| serde::de::Error::custom(format!(
+            "invalid block {:?}: expected a number, 0x hex or one of {}", text, BLOCK_TAGS.join(", "),
+        )))
+    }
+}
+
+impl BlockRef {
+    /// The concrete block number, looking tags up on `url`.
+    pub async fn resolve(&self, client: &reqwest::Client, url: &str) -> Result<u64, RpcError> {
+        let tag = match self {
+            BlockRef::Number(n) => return Ok(*n),
+            BlockRef::Tag(tag) => *tag,
+        };
+        let block = call(client, url, "eth_getBlockByNumber", serde_json::json!([tag, false])).await?;
+        block.get("number").and_then(quantity).map(|n| n.low_u64())
+            .ok_or_else(|| RpcError::Transport(format!("{} has no {} block", url, tag)))
+    }
+}
@@ -0,0 +1,135 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use crate::compiler::ContractData;
+use crate::diff;
+use crate::storage;
+
+/// A value before and after the simulated call.
+#[derive(Serialize)]
+pub struct Change {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotChange {
+    pub slot: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// The variables packed into the slot, when the account's contract is
+    /// known: `{ label, type, offset, before, after }`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub decoded: Vec<Value>,
+}
+
+/// Everything the call would change about one account.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountChange {
+    pub address: String,
+    /// The compiled contract whose layout decoded `storage`.
+    pub contract: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<Change>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<Change>,
+    pub code_changed: bool,
+    pub storage: Vec<SlotChange>,
+}
+
+/// The compiled contract whose runtime code, metadata aside, is `code`.
+/// Contracts with immutables won't match; name them in the request instead.
+pub fn identify<'a>(contracts: &'a [ContractData], code: &str) -> Option<&'a ContractData> {
+    let code = diff::hex_without_metadata(code.trim_start_matches("0x").to_string());
+    if code.is_empty() {
+        return None;
+    }
+    contracts.iter().find(|c| {
+        let runtime = c.artifact.deployed_bytecode.as_ref().and_then(|d| d.bytecode.as_ref());
+        runtime.is_some_and(|b| diff::without_metadata(&b.object) == code)
+    })
+}
+
+/// Turns a `prestateTracer` result in diff mode (`{ pre, post }`) into
+/// per-account changes. `contract_for` names the compiled contract at an
+/// address, given its code before the call, to decode storage against.
+pub fn changes<'a>(result: &Value, contract_for: impl Fn(&str, Option<&str>) -> Option<&'a ContractData>) -> Vec<AccountChange> {
+    let empty = serde_json::Map::new();
+    let pre = result.get("pre").and_then(Value::as_object).unwrap_or(&empty);
+    let post = result.get("post").and_then(Value::as_object).unwrap_or(&empty);
+    let addresses: BTreeSet<&String> = pre.keys().chain(post.keys()).collect();
+
+    addresses.into_iter().filter_map(|address| {
+        let before = pre.get(address);
+        let after = post.get(address);
+        // Diff mode leaves unchanged fields out of `post`, and a missing
+        // `post` entry means the account was deleted
+        let field = |account: Option<&Value>, name: &str| account?.get(name).map(|v| match v {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        });
+        let change = |name: &str| match after {
+            Some(_) => field(after, name).map(|new| Change { before: field(before, name), after: Some(new) }),
+            None => field(before, name).map(|old| Change { before: Some(old), after: None }),
+        };
+        let balance = change("balance");
+        let nonce = change("nonce");
+        let code_changed = after.is_none() || field(after, "code").is_some();
+
+        let code = field(before, "code");
+        let contract = contract_for(address, code.as_deref());
+        let layout = contract.and_then(|c| c.artifact.storage_layout.as_ref());
+        let mut keys: Vec<&String> = slots(before).into_iter().chain(slots(after)).flat_map(|s| s.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        let storage = keys.into_iter().filter_map(|slot| {
+            let old = slots(before).and_then(|s| s.get(slot)).and_then(Value::as_str);
+            // Cleared slots are left out of `post`
+            let new = slots(after).and_then(|s| s.get(slot)).and_then(Value::as_str);
+            if old == new {
+                return None;
+            }
+            let decoded = layout.zip(storage::parse_slot(slot))
+                .map(|(layout, index)| decode(layout, index, old, new))
+                .unwrap_or_default();
+            Some(SlotChange { slot: slot.clone(), before: old.map(str::to_string), after: new.map(str::to_string), decoded })
+        }).collect::<Vec<_>>();
+
+        if balance.is_none() && nonce.is_none() && !code_changed && storage.is_empty() {
+            return None;
+        }
+        Some(AccountChange {
+            address: address.clone(),
+            contract: contract.map(|c| c.name.clone()),
+            balance,
+            nonce,
+            code_changed,
+            storage,
+        })
+    }).collect()
+}
+
+fn slots(account: Option<&Value>) -> Option<&serde_json::Map<String, Value>> {
+    account?.get("storage")?.as_object()
+}
+
+/// Decodes both sides of a slot change, pairing up the variables in it.
+fn decode(layout: &foundry_compilers::artifacts::StorageLayout, slot: ethers::types::U256, before: Option<&str>, after: Option<&str>) -> Vec<Value> {
+    let word = |value: Option<&str>| storage::parse_word(&Value::from(value.unwrap_or("0x0"))).unwrap_or([0; 32]);
+    let old = storage::decode_slot(layout, slot, &word(before));
+    let new = storage::decode_slot(layout, slot, &word(after));
+    old.into_iter().zip(new).filter_map(|(old, new)| {
+        if old["value"] == new["value"] {
+            return None;
+        }
+        Some(serde_json::json!({
+            "label": old["label"],
+            "type": old["type"],
+            "offset": old["offset"],
+            "before": old["value"],
+            "after": new["value"],
+        }))
+    }).collect()
+}
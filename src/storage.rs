@@ -0,0 +1,162 @@
+use ethers::types::U256;
+use foundry_compilers::artifacts::{Storage, StorageLayout};
+use serde::Serialize;
+use crate::abi;
+
+/// Parses a slot given in decimal or `0x` hex, such as a precomputed
+/// `keccak256(key . slot)` mapping slot.
+pub fn parse_slot(s: &str) -> Option<U256> {
+    let s = s.trim();
+    match s.strip_prefix("0x") {
+        Some(hex) => U256::from_str_radix(hex, 16).ok(),
+        None => U256::from_dec_str(s).ok(),
+    }
+}
+
+pub fn slot_hex(slot: U256) -> String {
+    format!("0x{:064x}", slot)
+}
+
+/// Parses an `eth_getStorageAt` result into a word.
+pub fn parse_word(value: &serde_json::Value) -> Option<[u8; 32]> {
+    let bytes = abi::parse_hex(value.as_str()?).filter(|b| b.len() <= 32)?;
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(&bytes);
+    Some(word)
+}
+
+/// Decodes a variable packed into `word` at byte `offset` (counted from the
+/// right, as in solc's layout) taking `size` bytes.
+pub fn decode_packed(word: &[u8; 32], offset: usize, size: usize, ty: &str) -> Option<serde_json::Value> {
+    let end = 32usize.checked_sub(offset)?;
+    let slice = word.get(end.checked_sub(size)?..end)?;
+    let mut aligned = [0u8; 32];
+    if ty.starts_with("bytes") && ty != "bytes" {
+        aligned[..size].copy_from_slice(slice);
+    } else {
+        aligned[32 - size..].copy_from_slice(slice);
+    }
+    abi::decode_word(ty, U256::from_big_endian(&aligned))
+}
+
+/// Every layout entry stored at `slot`, decoded from `word`. Entries of
+/// types that can't be decoded from a single word get a `null` value.
+pub fn decode_slot(layout: &StorageLayout, slot: U256, word: &[u8; 32]) -> Vec<serde_json::Value> {
+    layout.storage.iter()
+        .filter(|var| parse_slot(&var.slot) == Some(slot))
+        .map(|var| {
+            let label = type_label(layout, var);
+            let value = match readable(layout, var) {
+                Readable::Slot { offset, size, .. } => decode_packed(word, offset, size, label),
+                Readable::Skipped(_) => None,
+            };
+            serde_json::json!({
+                "label": var.label,
+                "type": label,
+                "offset": var.offset,
+                "value": value,
+            })
+        })
+        .collect()
+}
+
+/// A layout entry that can be read from a single slot, or why it can't.
+pub enum Readable {
+    Slot { slot: U256, offset: usize, size: usize },
+    Skipped(&'static str),
+}
+
+pub fn readable(layout: &StorageLayout, var: &Storage) -> Readable {
+    let Some(ty) = layout.types.get(&var.storage_type) else { return Readable::Skipped("Unknown type") };
+    match ty.encoding.as_str() {
+        "mapping" => return Readable::Skipped("Mappings need a key; read the computed slot"),
+        "dynamic_array" => return Readable::Skipped("Dynamic arrays need an index; read the computed slot"),
+        "bytes" => return Readable::Skipped("Dynamic strings and bytes aren't read automatically"),
+        _ => {}
+    }
+    let (Some(slot), Ok(offset), Ok(size)) = (parse_slot(&var.slot), usize::try_from(var.offset), ty.number_of_bytes.parse::<usize>()) else {
+        return Readable::Skipped("Malformed layout entry");
+    };
+    if size > 32 {
+        return Readable::Skipped("Spans multiple slots");
+    }
+    Readable::Slot { slot, offset, size }
+}
+
+/// The type label (`uint256`, `mapping(address => uint256)`, ...) of `var`.
+pub fn type_label<'a>(layout: &'a StorageLayout, var: &'a Storage) -> &'a str {
+    layout.types.get(&var.storage_type).map_or(var.storage_type.as_str(), |t| t.label.as_str())
+}
+
+/// A layout entry without the compile-specific parts (AST ids, type ids),
+/// so layouts from different compiles can be compared.
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizedEntry {
+    pub label: String,
+    pub slot: String,
+    pub offset: i64,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub size: String,
+}
+
+/// `layout`'s entries in declaration order, inherited variables first.
+pub fn normalized(layout: &StorageLayout) -> Vec<NormalizedEntry> {
+    layout.storage.iter().map(|var| NormalizedEntry {
+        label: var.label.clone(),
+        slot: var.slot.clone(),
+        offset: var.offset,
+        ty: type_label(layout, var).to_string(),
+        size: layout.types.get(&var.storage_type).map(|t| t.number_of_bytes.clone()).unwrap_or_default(),
+    }).collect()
+}
+
+/// An old variable the new layout doesn't keep in place.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutIssue {
+    /// `removed`, `moved` (different slot or offset) or `retyped`.
+    pub kind: &'static str,
+    pub old: NormalizedEntry,
+    pub new: Option<NormalizedEntry>,
+}
+
+/// Whether a proxy can switch from `old` to `new` without corrupting state.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Compatibility {
+    pub compatible: bool,
+    pub issues: Vec<LayoutIssue>,
+    /// Same slot, offset and type under a new name: safe, but worth a look.
+    pub renamed: Vec<NormalizedEntry>,
+    /// Variables declared after every old one.
+    pub appended: Vec<NormalizedEntry>,
+}
+
+/// Checks that `new` keeps every variable of `old` at the same position with
+/// the same type, only appending after them. Variables are paired by
+/// declaration order, so inserting one shifts the rest and reports them all.
+pub fn compatibility(old: &StorageLayout, new: &StorageLayout) -> Compatibility {
+    let (old, mut new) = (normalized(old), normalized(new));
+    let appended = new.split_off(old.len().min(new.len()));
+    let mut issues: Vec<LayoutIssue> = old.iter().skip(new.len())
+        .map(|var| LayoutIssue { kind: "removed", old: var.clone(), new: None })
+        .collect();
+    let mut renamed = Vec::new();
+    for (before, after) in old.into_iter().zip(new) {
+        let kind = if (&before.slot, before.offset) != (&after.slot, after.offset) {
+            "moved"
+        } else if (&before.ty, &before.size) != (&after.ty, &after.size) {
+            "retyped"
+        } else {
+            if before.label != after.label {
+                renamed.push(after);
+            }
+            continue;
+        };
+        issues.push(LayoutIssue { kind, old: before, new: Some(after) });
+    }
+    issues.sort_by_key(|issue| parse_slot(&issue.old.slot));
+    Compatibility { compatible: issues.is_empty(), issues, renamed, appended }
+}
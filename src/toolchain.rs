@@ -0,0 +1,62 @@
+use serde::Serialize;
+use std::process::Command;
+
+/// Foundry binaries chasm shells out to.
+pub const FOUNDRY_TOOLS: [&str; 3] = ["forge", "cast", "anvil"];
+
+/// Tools that are nice to have but not required; foundry-compilers can
+/// install solc by itself when svm is missing, and only `/analyze` needs
+/// slither.
+pub const OPTIONAL_TOOLS: [&str; 2] = ["svm", "slither"];
+
+#[derive(Clone, Serialize)]
+pub struct ToolInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub available: bool,
+}
+
+/// Runs `<tool> --version` for each tool once. Meant to be called at startup
+/// and cached, since spawning processes per request is wasteful.
+pub fn detect(tools: &[&str]) -> Vec<ToolInfo> {
+    tools.iter().map(|name| {
+        let version = tool_version(name);
+        ToolInfo {
+            name: name.to_string(),
+            available: version.is_some(),
+            version,
+        }
+    }).collect()
+}
+
+/// Detects every known tool and logs what was found, warning about
+/// missing required tools so failures don't first surface deep in a handler.
+pub fn detect_and_report() -> Vec<ToolInfo> {
+    let required = detect(&FOUNDRY_TOOLS);
+    let optional = detect(&OPTIONAL_TOOLS);
+
+    for tool in &required {
+        match &tool.version {
+            Some(version) => tracing::info!("Found {}: {}", tool.name, version),
+            None => tracing::warn!("!!! {} not found on PATH. Install Foundry (https://getfoundry.sh) or related features will fail !!!", tool.name),
+        }
+    }
+    for tool in &optional {
+        match &tool.version {
+            Some(version) => tracing::info!("Found {}: {}", tool.name, version),
+            None if tool.name == "svm" => tracing::info!("svm not found on PATH; solc versions will be downloaded by foundry-compilers instead"),
+            None => tracing::info!("Optional tool {} not found on PATH", tool.name),
+        }
+    }
+
+    required.into_iter().chain(optional).collect()
+}
+
+fn tool_version(name: &str) -> Option<String> {
+    let out = Command::new(name).arg("--version").output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    stdout.lines().next().map(|line| line.trim().to_string())
+}
@@ -1,51 +1,109 @@
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher, Config};
-use std::path::PathBuf;
-use tokio::sync::broadcast;
-use std::sync::{Arc, Mutex};
-use crate::compiler::Compiler;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use crate::cache::CONFIG_FILES;
+use crate::compiler::CompileState;
+use crate::ws::WsMessage;
 
+const MIN_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Watches the project at `path` plus the state's `--watch` directories.
+/// Events from every directory recompile the one project.
 pub async fn setup_watcher(
     path: PathBuf,
-    tx: broadcast::Sender<String>,
-    last_msg: Arc<Mutex<Option<String>>>,
+    compile_state: Arc<CompileState>,
 ) -> notify::Result<()> {
-    let path_clone = path.clone();
-    let tx_clone = tx.clone();
-    let last_msg_clone = last_msg.clone();
-
+    let compiles = spawn_compile_worker(compile_state.clone(), path.clone());
     tokio::task::spawn_blocking(move || {
-        let mut watcher = RecommendedWatcher::new(move |res: notify::Result<Event>| {
-            match res {
-                Ok(event) => {
-                    let is_sol = event.paths.iter().any(|p| p.extension().map_or(false, |ext| ext == "sol"));
-                    if is_sol {
-                         tracing::info!("Change detected in: {:?}", event.paths);
-                         
-                         let compiler = Compiler::new(path_clone.clone()).unwrap(); 
-                         match compiler.compile_to_json() {
-                             Ok(json) => {
-                                 tracing::info!("Compilation successful");
-                                 if let Ok(mut lock) = last_msg_clone.lock() {
-                                     *lock = Some(json.clone());
-                                 }
-                                 let _ = tx_clone.send(json);
-                             }
-                             Err(e) => {
-                                 tracing::error!("Compilation failed: {}", e);
-                                 let _ = tx_clone.send(format!("{{\"type\": \"compile_error\", \"error\": \"{}\"}}", e));
-                             }
-                         }
-                    }
-                },
-                Err(e) => tracing::error!("watch error: {:?}", e),
+        let mut backoff = MIN_RETRY_BACKOFF;
+        loop {
+            let started = Instant::now();
+            if let Err(e) = watch(&path, compile_state.extra_dirs(), &compile_state, &compiles) {
+                tracing::error!("File watcher on {} failed: {}", path.display(), e);
+            } else {
+                tracing::error!("File watcher on {} stopped", path.display());
             }
-        }, Config::default()).unwrap();
-
-        watcher.watch(path.as_path(), RecursiveMode::Recursive).unwrap();
-        
-        // Keep the watcher alive
-        std::thread::park();
+            // Reset the backoff if the watcher had been up for a while
+            if started.elapsed() >= MAX_RETRY_BACKOFF {
+                backoff = MIN_RETRY_BACKOFF;
+            }
+            tracing::info!("Restarting file watcher in {:?}", backoff);
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+        }
     });
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Watches `root` and `extra_dirs` and handles their events until the
+/// watcher shuts down. Events arrive over a channel, so they're handled on
+/// this thread and a panic while handling one is caught here instead of
+/// killing notify's thread.
+fn watch(root: &Path, extra_dirs: &[PathBuf], compile_state: &CompileState, compiles: &mpsc::Sender<Vec<PathBuf>>) -> notify::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    watcher.watch(root, RecursiveMode::Recursive)?;
+    tracing::info!("Watching {} for changes", root.display());
+    // Directories inside the root are already covered
+    for dir in extra_dirs.iter().filter(|dir| !dir.starts_with(root)) {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        tracing::info!("Watching {} for changes", dir.display());
+    }
+
+    for res in rx {
+        match res {
+            Ok(event) => {
+                let handled = catch_unwind(AssertUnwindSafe(|| handle_event(root, compile_state, compiles, &event)));
+                if handled.is_err() {
+                    tracing::error!("Panicked while handling change to {:?}", event.paths);
+                }
+            }
+            Err(e) => tracing::error!("watch error: {:?}", e),
+        }
+    }
+    Ok(())
+}
+
+fn handle_event(root: &Path, compile_state: &CompileState, compiles: &mpsc::Sender<Vec<PathBuf>>, event: &Event) {
+    // remappings.txt and foundry.toml's via_ir (the only setting read from
+    // it) shape the Compiler, so they rebuild it just like a source edit
+    let config = event.paths.iter()
+        .filter(|p| p.parent() == Some(root))
+        .filter_map(|p| p.file_name()?.to_str())
+        .find(|name| CONFIG_FILES.contains(name));
+    if let Some(file) = config {
+        tracing::info!("Config change detected in: {}", file);
+        compile_state.broadcast(&WsMessage::ConfigChanged { file: file.to_string() });
+        let _ = compiles.send(vec![root.join(file)]);
+        return;
+    }
+    let is_sol = event.paths.iter().any(|p| p.extension().is_some_and(|ext| ext == "sol"));
+    if is_sol {
+        tracing::info!("Change detected in: {:?}", event.paths);
+        let _ = compiles.send(event.paths.clone());
+    }
+}
+
+/// Starts the thread that compiles `root` for the watcher, one compile at
+/// a time, so it sees the next change right away instead of after a
+/// compile. Changes sent while a compile runs are merged into a single
+/// compile after it; one save usually fires several events.
+fn spawn_compile_worker(compile_state: Arc<CompileState>, root: PathBuf) -> mpsc::Sender<Vec<PathBuf>> {
+    let (tx, rx) = mpsc::channel::<Vec<PathBuf>>();
+    std::thread::spawn(move || {
+        while let Ok(mut changed) = rx.recv() {
+            changed.extend(rx.try_iter().flatten());
+            changed.sort();
+            changed.dedup();
+            let compiled = catch_unwind(AssertUnwindSafe(|| compile_state.compile_and_publish(root.clone(), &changed)));
+            if compiled.is_err() {
+                tracing::error!("Panicked while compiling changes to {:?}", changed);
+            }
+        }
+    });
+    tx
+}
@@ -0,0 +1,371 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, Notify};
+use crate::compiler::{CompileSettings, ContractData, ContractFilter};
+
+/// Bumped whenever the shape of an existing message changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Topics clients can subscribe to. Clients that never send a subscription
+/// receive every channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    Compile,
+    Anvil,
+    Trace,
+}
+
+impl Channel {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "compile" => Some(Channel::Compile),
+            "anvil" | "anvil_logs" => Some(Channel::Anvil),
+            "trace" => Some(Channel::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Compile => "compile",
+            Channel::Anvil => "anvil",
+            Channel::Trace => "trace",
+        }
+    }
+}
+
+/// How a message is treated in a client's outbound queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// `compile_success` and `no_sources`: only the newest matters, so it
+    /// replaces any older one still queued.
+    Latest,
+    /// Trace output lines, dropped oldest first when the queue is full.
+    Droppable,
+    /// Everything else. These are rare enough to never drop.
+    Always,
+}
+
+/// A serialized message as carried on the broadcast channel.
+#[derive(Clone)]
+pub struct Broadcast {
+    pub channel: Channel,
+    pub delivery: Delivery,
+    pub json: String,
+}
+
+/// One client's outbound queue, drained by a writer task so a slow
+/// connection backs up here instead of stalling the loop feeding it.
+pub struct Outbox<T> {
+    queue: Mutex<VecDeque<(Delivery, T)>>,
+    ready: Notify,
+    capacity: usize,
+}
+
+impl<T> Outbox<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self { queue: Mutex::new(VecDeque::new()), ready: Notify::new(), capacity }
+    }
+
+    /// Queues `item`, returning how many queued messages were dropped to
+    /// make room. When the queue is full the oldest droppable message goes,
+    /// or `item` itself if it's droppable and nothing else can.
+    pub fn push(&self, delivery: Delivery, item: T) -> usize {
+        let mut queue = self.queue.lock().unwrap();
+        let before = queue.len();
+        if delivery == Delivery::Latest {
+            queue.retain(|(queued, _)| *queued != Delivery::Latest);
+        }
+        let mut dropped = before - queue.len();
+        if queue.len() >= self.capacity {
+            match queue.iter().position(|(queued, _)| *queued == Delivery::Droppable) {
+                Some(oldest) => {
+                    queue.remove(oldest);
+                    dropped += 1;
+                }
+                None if delivery == Delivery::Droppable => return dropped + 1,
+                None => {}
+            }
+        }
+        queue.push_back((delivery, item));
+        drop(queue);
+        self.ready.notify_one();
+        dropped
+    }
+
+    /// Messages waiting to be written.
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// Waits for the next message.
+    pub async fn pop(&self) -> T {
+        loop {
+            if let Some((_, item)) = self.queue.lock().unwrap().pop_front() {
+                return item;
+            }
+            self.ready.notified().await;
+        }
+    }
+}
+
+/// A connected client, as kept in `Connections`.
+pub struct ConnectionHandle<T> {
+    pub outbox: Arc<Outbox<T>>,
+    /// `None` until the client subscribes, meaning every channel.
+    pub subscriptions: Option<HashSet<Channel>>,
+    /// Unix seconds.
+    pub connected_at: u64,
+}
+
+/// What `/connections` reports about a client.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub connected_at: u64,
+    pub subscriptions: Option<Vec<&'static str>>,
+    /// Messages waiting in its outbound queue.
+    pub queued: usize,
+}
+
+/// Every open WebSocket connection, by id. Ids count up from 1 and aren't
+/// reused while the server runs.
+pub struct Connections<T> {
+    next_id: u64,
+    connections: HashMap<u64, ConnectionHandle<T>>,
+}
+
+impl<T> Default for Connections<T> {
+    fn default() -> Self {
+        Self { next_id: 0, connections: HashMap::new() }
+    }
+}
+
+impl<T> Connections<T> {
+    /// Adds a connection for as long as the returned guard lives, so it's
+    /// removed however the connection's handler ends.
+    pub fn register(this: &Mutex<Self>, outbox: Arc<Outbox<T>>) -> Registration<'_, T> {
+        let mut connections = this.lock().unwrap();
+        connections.next_id += 1;
+        let id = connections.next_id;
+        let connected_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        connections.connections.insert(id, ConnectionHandle { outbox, subscriptions: None, connected_at });
+        Registration { connections: this, id }
+    }
+
+    pub fn set_subscriptions(&mut self, id: u64, subscriptions: HashSet<Channel>) {
+        if let Some(connection) = self.connections.get_mut(&id) {
+            connection.subscriptions = Some(subscriptions);
+        }
+    }
+
+    /// Open connections, oldest first.
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut listed: Vec<ConnectionInfo> = self.connections.iter().map(|(id, c)| ConnectionInfo {
+            id: *id,
+            connected_at: c.connected_at,
+            subscriptions: c.subscriptions.as_ref().map(|subs| {
+                let mut names: Vec<&'static str> = subs.iter().map(|ch| ch.name()).collect();
+                names.sort();
+                names
+            }),
+            queued: c.outbox.len(),
+        }).collect();
+        listed.sort_by_key(|c| c.id);
+        listed
+    }
+}
+
+pub struct Registration<'a, T> {
+    connections: &'a Mutex<Connections<T>>,
+    pub id: u64,
+}
+
+impl<T> Drop for Registration<'_, T> {
+    fn drop(&mut self) {
+        // Poisoned by a panic elsewhere: the entry can't be listed anyway
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.connections.remove(&self.id);
+        }
+    }
+}
+
+/// Every payload pushed to WebSocket clients. Serialized with an internal
+/// `type` tag plus a top-level `version` field.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsMessage {
+    CompileSuccess {
+        contracts: Vec<ContractData>,
+        /// The solc version used, when every file was compiled by the same one.
+        #[serde(rename = "solcVersion")]
+        solc_version: Option<String>,
+        /// Per-file versions, only when more than one solc was used.
+        #[serde(rename = "solcVersions", skip_serializing_if = "BTreeMap::is_empty")]
+        solc_versions: BTreeMap<String, String>,
+        /// Wall-clock time of the compile, or of loading the cache.
+        #[serde(rename = "durationMs")]
+        duration_ms: u64,
+        /// Distinct source files that went into the artifacts, imports included.
+        #[serde(rename = "fileCount")]
+        file_count: usize,
+        /// Whether the artifacts came from the on-disk cache rather than solc.
+        cached: bool,
+        /// The optimizer, EVM version and pipeline solc was given.
+        settings: CompileSettings,
+        /// Contracts left out by `--contracts-include`/`--contracts-exclude`.
+        #[serde(rename = "hiddenCount", skip_serializing_if = "is_zero")]
+        hidden_count: usize,
+        /// Sent contracts whose deployed code is over `--size-limit`.
+        #[serde(rename = "sizeWarnings", skip_serializing_if = "Vec::is_empty")]
+        size_warnings: Vec<SizeWarning>,
+    },
+    CompileError {
+        error: String,
+    },
+    /// A compile has started; its result follows.
+    Compiling {
+        /// The changed files that prompted it, relative to the root; empty
+        /// for the initial compile and explicit requests.
+        files: Vec<String>,
+    },
+    /// The source directory and `--watch` directories have no `.sol`
+    /// files to compile.
+    NoSources {
+        dir: PathBuf,
+        #[serde(rename = "watchDirs")]
+        watch_dirs: Vec<PathBuf>,
+    },
+    /// `foundry.toml` or `remappings.txt` changed; a recompile follows.
+    ConfigChanged {
+        file: String,
+    },
+    AnvilDied {
+        node: String,
+        port: u16,
+        status: String,
+        restarting: bool,
+    },
+    /// One line of `cast run` output for the trace of `hash`.
+    TraceLine {
+        hash: String,
+        stream: &'static str,
+        line: String,
+    },
+    TraceDone {
+        hash: String,
+        success: bool,
+        status: String,
+    },
+}
+
+/// A contract too big to deploy where EIP-170 (or `--size-limit`) applies.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeWarning {
+    pub contract: String,
+    pub file: Option<String>,
+    pub deployed_size: usize,
+    pub limit: usize,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    version: u32,
+    #[serde(flatten)]
+    message: &'a WsMessage,
+}
+
+impl WsMessage {
+    /// Summarizes which solc compiled `contracts`, and how, alongside the
+    /// ones `filter` shows. The summary covers every contract; size warnings
+    /// only the ones shown.
+    pub fn compile_success(contracts: &[ContractData], filter: &ContractFilter, size_limit: usize, duration: Duration, cached: bool, settings: CompileSettings) -> Self {
+        let file_count = contracts.iter()
+            .flat_map(|c| c.source_list.iter())
+            .filter(|f| !f.is_empty())
+            .collect::<BTreeSet<_>>()
+            .len();
+        let per_file: BTreeMap<String, String> = contracts.iter()
+            .filter_map(|c| Some((c.source_file()?.to_string(), c.solc_version.clone()?)))
+            .collect();
+        let distinct: BTreeSet<&String> = per_file.values().collect();
+        let (solc_version, solc_versions) = match distinct.len() {
+            0 | 1 => (distinct.first().map(|v| v.to_string()), BTreeMap::new()),
+            _ => (None, per_file),
+        };
+        let shown: Vec<ContractData> = contracts.iter().filter(|c| filter.shows(c)).cloned().collect();
+        let size_warnings = shown.iter()
+            .filter(|c| c.deployed_size > size_limit)
+            .map(|c| SizeWarning {
+                contract: c.name.clone(),
+                file: c.source_file().map(str::to_string),
+                deployed_size: c.deployed_size,
+                limit: size_limit,
+            })
+            .collect();
+        WsMessage::CompileSuccess {
+            size_warnings,
+            hidden_count: contracts.len() - shown.len(),
+            contracts: shown,
+            solc_version,
+            solc_versions,
+            duration_ms: duration.as_millis() as u64,
+            file_count,
+            cached,
+            settings,
+        }
+    }
+
+    pub fn channel(&self) -> Channel {
+        match self {
+            WsMessage::CompileSuccess { .. }
+            | WsMessage::CompileError { .. }
+            | WsMessage::Compiling { .. }
+            | WsMessage::NoSources { .. }
+            | WsMessage::ConfigChanged { .. } => Channel::Compile,
+            WsMessage::AnvilDied { .. } => Channel::Anvil,
+            WsMessage::TraceLine { .. } | WsMessage::TraceDone { .. } => Channel::Trace,
+        }
+    }
+
+    pub fn delivery(&self) -> Delivery {
+        match self {
+            WsMessage::CompileSuccess { .. } | WsMessage::NoSources { .. } => Delivery::Latest,
+            WsMessage::TraceLine { .. } => Delivery::Droppable,
+            WsMessage::Compiling { .. }
+            | WsMessage::CompileError { .. }
+            | WsMessage::ConfigChanged { .. }
+            | WsMessage::AnvilDied { .. }
+            | WsMessage::TraceDone { .. } => Delivery::Always,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let envelope = Envelope { version: PROTOCOL_VERSION, message: self };
+        serde_json::to_string(&envelope).unwrap_or_else(|e| {
+            tracing::error!("Failed to serialize WebSocket message: {}", e);
+            serde_json::json!({
+                "type": "compile_error",
+                "version": PROTOCOL_VERSION,
+                "error": format!("Failed to serialize message: {}", e),
+            }).to_string()
+        })
+    }
+}
+
+/// Serializes `msg` and sends it to all connected clients, returning the JSON
+/// so callers can also cache it.
+pub fn broadcast(tx: &broadcast::Sender<Broadcast>, msg: &WsMessage) -> String {
+    let json = msg.to_json();
+    let _ = tx.send(Broadcast { channel: msg.channel(), delivery: msg.delivery(), json: json.clone() });
+    json
+}